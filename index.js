@@ -1,8 +1,9 @@
-import { init, authenticate_post, authenticate_post_with_doc } from "./pkg";
+import { VerifierClient } from "./js/client.js";
+
+const verifier = new VerifierClient();
 
 async function main() {
-  init();
-  await authenticate_post_with_doc(
+  await verifier.authenticate_post_with_doc(
     "at://did:plc:ia76kvnndjutgedggx2ibrem/app.bsky.feed.post/3lbckva5qqksv",
     "bafyreieiof45ascpryjyodac4j5chhi5o4svygejbm4tp2hfouitxpi7ba",
     {
@@ -73,4 +74,5 @@ async function main() {
 }
 
 await main();
+verifier.terminate();
 console.log("done!");
@@ -0,0 +1,149 @@
+// Runs the verifier in Web Workers, with the same functions as the wasm
+// module but always asynchronous:
+//
+//   const verifier = new VerifierClient();
+//   const report = await verifier.authenticate_post(uri, cid, record);
+//
+// `VerifierPool` spreads calls over several workers to verify in parallel.
+// Each worker keeps its own rev store, so configure them all with
+// `pool.broadcast("use_indexed_db_rev_store", name)` to share one.
+
+// The functions forwarded as methods; anything else exported by the module
+// can still be reached with `call`.
+export const METHODS = [
+  "authenticate_post",
+  "authenticate_post_with_doc",
+  "verify_record",
+  "verify_record_with_doc",
+  "verify_post",
+  "verify_like",
+  "verify_repost",
+  "verify_follow",
+  "verify_list",
+  "verify_feed_generator",
+  "verify_profile",
+  "fetch_verified_record",
+  "verify_thread",
+  "verify_blob",
+  "verify_record_blobs",
+  "verify_record_in_archive",
+  "use_memory_rev_store",
+  "use_indexed_db_rev_store",
+  "clear_rev_store",
+];
+
+function createWorker() {
+  return new Worker(new URL("./worker.js", import.meta.url), {
+    type: "module",
+  });
+}
+
+function deserializeError(message) {
+  if ("thrown" in message) {
+    return message.thrown;
+  }
+  const { name, message: text, ...properties } = message.error;
+  const error = new Error(text);
+  error.name = name;
+  return Object.assign(error, properties);
+}
+
+export class VerifierClient {
+  constructor(worker = createWorker()) {
+    this.worker = worker;
+    this.nextId = 0;
+    this.pending = new Map();
+    this.worker.onmessage = (event) => {
+      const message = event.data;
+      const call = this.pending.get(message.id);
+      if (!call) {
+        return;
+      }
+      this.pending.delete(message.id);
+      call.signal?.removeEventListener("abort", call.onAbort);
+      if ("result" in message) {
+        call.resolve(message.result);
+      } else {
+        call.reject(deserializeError(message));
+      }
+    };
+  }
+
+  // Calls `method(...args)` in the worker. An `AbortSignal` in the options
+  // (the last argument) cancels the call's requests in the worker.
+  call(method, ...args) {
+    const id = this.nextId++;
+    let signal;
+    let signalArg;
+    const last = args.length - 1;
+    if (args[last]?.signal instanceof AbortSignal) {
+      const { signal: callSignal, ...options } = args[last];
+      signal = callSignal;
+      args[last] = options;
+      signalArg = last;
+    }
+
+    return new Promise((resolve, reject) => {
+      const onAbort = () => this.worker.postMessage({ id, cancel: true });
+      this.pending.set(id, { resolve, reject, signal, onAbort });
+      signal?.addEventListener("abort", onAbort);
+      this.worker.postMessage({ id, method, args, signalArg });
+      if (signal?.aborted) {
+        onAbort();
+      }
+    });
+  }
+
+  // Calls still waiting for the worker.
+  get load() {
+    return this.pending.size;
+  }
+
+  terminate() {
+    this.worker.terminate();
+    for (const call of this.pending.values()) {
+      call.reject(new Error("verifier terminated"));
+    }
+    this.pending.clear();
+  }
+}
+
+export class VerifierPool {
+  constructor({
+    size = Math.min(Math.max((navigator.hardwareConcurrency ?? 2) - 1, 1), 4),
+    createWorker: create = createWorker,
+  } = {}) {
+    this.clients = Array.from(
+      { length: size },
+      () => new VerifierClient(create()),
+    );
+  }
+
+  // Runs on the worker with the fewest calls in flight.
+  call(method, ...args) {
+    const client = this.clients.reduce((best, client) =>
+      client.load < best.load ? client : best,
+    );
+    return client.call(method, ...args);
+  }
+
+  // Runs on every worker, e.g. to configure its rev store.
+  broadcast(method, ...args) {
+    return Promise.all(
+      this.clients.map((client) => client.call(method, ...args)),
+    );
+  }
+
+  terminate() {
+    this.clients.forEach((client) => client.terminate());
+  }
+}
+
+for (const method of METHODS) {
+  VerifierClient.prototype[method] = function (...args) {
+    return this.call(method, ...args);
+  };
+  VerifierPool.prototype[method] = function (...args) {
+    return this.call(method, ...args);
+  };
+}
@@ -0,0 +1,2 @@
+export * from "../pkg-deno/public_transport.d.ts";
+export { FileRevStore } from "./file_rev_store.d.mts";
@@ -0,0 +1,17 @@
+// @ts-self-types="./deno.d.ts"
+
+// Entry point for Deno. Build the module first with `npm run build:deno`:
+//
+//   import { verify_record } from "./js/deno.js";
+//
+// Network access to DID directories and PDSes needs `--allow-net`, and
+// loading the wasm file `--allow-read`. There is no IndexedDB, so remember
+// revs with `use_memory_rev_store`, or across runs with
+// `use_rev_store(new FileRevStore(path))`.
+
+import { init } from "../pkg-deno/public_transport.js";
+
+init();
+
+export * from "../pkg-deno/public_transport.js";
+export { FileRevStore } from "./file_rev_store.mjs";
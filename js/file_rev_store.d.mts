@@ -0,0 +1,11 @@
+interface RevMark {
+  rev: string;
+  cid: string;
+}
+
+/** A `RevStore` for `use_rev_store` that keeps the marks in a JSON file. */
+export declare class FileRevStore {
+  constructor(path: string);
+  get(did: string): Promise<RevMark | null>;
+  put(did: string, mark: RevMark): Promise<RevMark | null>;
+}
@@ -0,0 +1,87 @@
+// A rev store for `use_rev_store` that keeps the marks in a JSON file, for
+// Node and Deno where there is no IndexedDB:
+//
+//   use_rev_store(new FileRevStore("revs.json"));
+//
+// Each `put` holds a lock file next to the store while it reads, compares
+// and writes, so processes and workers sharing the file can't lower a stored
+// rev. Deno needs `--allow-read` and `--allow-write` for the file.
+
+import { open, readFile, rename, unlink, writeFile } from "node:fs/promises";
+
+const LOCK_TIMEOUT_MS = 10_000;
+
+function sleep(ms) {
+  return new Promise((resolve) => setTimeout(resolve, ms));
+}
+
+export class FileRevStore {
+  constructor(path) {
+    this.path = path;
+    this.queue = Promise.resolve();
+  }
+
+  async get(did) {
+    return (await this.read())[did] ?? null;
+  }
+
+  // Stores `mark` unless the stored rev is at least as new, and returns the
+  // mark stored before. Tids sort as strings.
+  put(did, mark) {
+    const result = this.queue.then(() =>
+      this.locked(async () => {
+        const marks = await this.read();
+        const stored = marks[did] ?? null;
+        if (!stored || stored.rev < mark.rev) {
+          marks[did] = { rev: mark.rev, cid: mark.cid };
+          await this.write(marks);
+        }
+        return stored;
+      }),
+    );
+    this.queue = result.catch(() => {});
+    return result;
+  }
+
+  async read() {
+    try {
+      return JSON.parse(await readFile(this.path, "utf8"));
+    } catch (err) {
+      if (err.code === "ENOENT") {
+        return {};
+      }
+      throw err;
+    }
+  }
+
+  // written aside and renamed over, so readers never see half a file
+  async write(marks) {
+    const temp = `${this.path}.tmp`;
+    await writeFile(temp, JSON.stringify(marks));
+    await rename(temp, this.path);
+  }
+
+  async locked(f) {
+    const lock = `${this.path}.lock`;
+    const started = Date.now();
+    for (;;) {
+      try {
+        await (await open(lock, "wx")).close();
+        break;
+      } catch (err) {
+        if (err.code !== "EEXIST") {
+          throw err;
+        }
+        if (Date.now() - started > LOCK_TIMEOUT_MS) {
+          throw new Error(`couldn't lock ${lock}, remove it if it is stale`);
+        }
+        await sleep(10);
+      }
+    }
+    try {
+      return await f();
+    } finally {
+      await unlink(lock);
+    }
+  }
+}
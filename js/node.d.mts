@@ -0,0 +1,2 @@
+export * from "../pkg-node/public_transport.js";
+export { FileRevStore } from "./file_rev_store.d.mts";
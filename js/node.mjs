@@ -0,0 +1,14 @@
+// Entry point for Node.js 18 or newer, which has a global `fetch`. Build the
+// module first with `npm run build:node`:
+//
+//   import { verify_record } from "./js/node.mjs";
+//
+// There is no IndexedDB here, so remember revs with `use_memory_rev_store`,
+// or across runs with `use_rev_store(new FileRevStore(path))`.
+
+import { init } from "../pkg-node/public_transport.js";
+
+init();
+
+export * from "../pkg-node/public_transport.js";
+export { FileRevStore } from "./file_rev_store.mjs";
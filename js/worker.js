@@ -0,0 +1,60 @@
+// Hosts the verifier in a Web Worker, so hashing and signature checks never
+// block the page. Speaks the protocol of `client.js`:
+//
+//   -> { id, method, args, signalArg }   call `method(...args)`
+//   -> { id, cancel: true }              abort the call's requests
+//   <- { id, result } | { id, error } | { id, thrown }
+//
+// `signalArg` is the index of the options argument that had an `AbortSignal`,
+// which can't cross threads; the worker puts its own signal there instead.
+
+const verifier = import("../pkg").then((module) => {
+  module.init();
+  return module;
+});
+
+const controllers = new Map();
+
+// Errors lose their class and extra properties (`status`, `retryAfter`, ...)
+// when cloned, so they are flattened. Anything else, like the verifier's
+// string errors, is passed through as `thrown`.
+function serializeError(err) {
+  if (!(err instanceof Error)) {
+    return { thrown: err };
+  }
+  return { error: { ...err, name: err.name, message: err.message } };
+}
+
+async function call({ id, method, args, signalArg }) {
+  // set up before waiting for the module, so an early cancel isn't lost
+  if (signalArg !== undefined) {
+    const controller = new AbortController();
+    controllers.set(id, controller);
+    args[signalArg] = { ...args[signalArg], signal: controller.signal };
+  }
+
+  try {
+    const module = await verifier;
+    if (method.startsWith("__") || typeof module[method] !== "function") {
+      throw new Error(`unknown method ${method}`);
+    }
+    return await module[method](...args);
+  } finally {
+    controllers.delete(id);
+  }
+}
+
+self.onmessage = async (event) => {
+  const message = event.data;
+  if (message.cancel) {
+    controllers.get(message.id)?.abort();
+    return;
+  }
+
+  try {
+    const result = await call(message);
+    self.postMessage({ id: message.id, result });
+  } catch (err) {
+    self.postMessage({ id: message.id, ...serializeError(err) });
+  }
+};
@@ -0,0 +1,96 @@
+use crate::syntax::{Did, Tid};
+use crate::transport::{fetch, FetchOptions};
+use crate::xrpc;
+use cid::Cid;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+async fn fetch_json<T: DeserializeOwned>(
+    url: &str,
+    fetch_options: &FetchOptions,
+) -> Result<T, JsValue> {
+    let resp = fetch(url, fetch_options).await?;
+    xrpc::expect_json(url, &resp)?;
+    let json = resp.read(JsFuture::from(resp.json()?)).await?;
+    Ok(serde_wasm_bindgen::from_value(json)?)
+}
+
+/// The output of `com.atproto.sync.getRepoStatus`.
+#[derive(Deserialize)]
+struct RepoStatus {
+    active: bool,
+    status: Option<String>,
+}
+
+/// The output of `com.atproto.sync.getLatestCommit`.
+#[derive(Deserialize, Serialize)]
+pub struct LatestCommit {
+    pub cid: String,
+    pub rev: String,
+}
+
+/// What the PDS says about an account beyond its signed records: whether it
+/// is active (and if not, why, e.g. `takendown` or `deactivated`) and which
+/// commit is the newest.
+pub struct AccountStatus {
+    pub active: bool,
+    pub status: Option<String>,
+    pub latest_commit: Option<LatestCommit>,
+}
+
+impl AccountStatus {
+    /// Queries `getRepoStatus`, then `getLatestCommit` if the account is
+    /// active, since inactive repos aren't served.
+    pub async fn fetch(
+        pds: &str,
+        did: &Did,
+        fetch_options: &FetchOptions,
+    ) -> Result<AccountStatus, JsValue> {
+        let status: RepoStatus = fetch_json(
+            &format!("{}/xrpc/com.atproto.sync.getRepoStatus?did={}", pds, did),
+            fetch_options,
+        )
+        .await?;
+
+        let latest_commit = if status.active {
+            Some(
+                fetch_json(
+                    &format!("{}/xrpc/com.atproto.sync.getLatestCommit?did={}", pds, did),
+                    fetch_options,
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        Ok(AccountStatus {
+            active: status.active,
+            status: status.status,
+            latest_commit,
+        })
+    }
+
+    /// Whether the commit `getRecord` proved against is the latest one. A
+    /// newer latest commit just means the repo moved on in between, but an
+    /// older one means the PDS is contradicting itself.
+    pub fn is_latest(&self, rev: &str, cid: &Cid) -> Result<Option<bool>, JsValue> {
+        let latest = match &self.latest_commit {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        let latest_rev =
+            Tid::from_str(&latest.rev).map_err(|_| "latest commit rev is not a valid tid")?;
+        let rev = Tid::from_str(rev).map_err(|_| "commit rev is not a valid tid")?;
+        if latest_rev < rev {
+            return Err("getRecord returned a commit newer than getLatestCommit".into());
+        }
+        if latest_rev == rev && latest.cid != cid.to_string() {
+            return Err("getRecord and getLatestCommit disagree on the commit for a rev".into());
+        }
+        Ok(Some(latest_rev == rev))
+    }
+}
@@ -0,0 +1,245 @@
+use crate::syntax::Did;
+use crate::transport::{fetch, read_chunks, FetchOptions};
+use crate::value::{self, Value};
+use crate::{get_did_doc, hasher, record_to_cbor, DidDocument, VerifyOptions};
+use cid::Cid;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use web_sys::Response;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_BLOB: &str = r#"
+export type BlobRef =
+  | string
+  | {
+      $type: "blob";
+      ref: { $link: string };
+      mimeType: string;
+      size: number;
+    }
+  | { cid: string; mimeType: string };
+
+export function verify_blob(did: string, blob: BlobRef, bytes?: Uint8Array | null, options?: VerifyOptions): Promise<void>;
+export function verify_record_blobs(did: string, record: AtprotoRecord, options?: VerifyOptions): Promise<void>;
+"#;
+
+/// What a record says about a blob it embeds.
+pub struct BlobRef {
+    pub cid: Cid,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+}
+
+impl BlobRef {
+    /// Reads a blob ref out of `value`, if it is shaped like one. A
+    /// `$type: "blob"` map with a missing `ref` or a negative `size` is an
+    /// error rather than something else.
+    fn from_value(value: &Value) -> Result<Option<BlobRef>, JsValue> {
+        let map = match value {
+            Value::Map(map) => map,
+            _ => return Ok(None),
+        };
+        let mime_type = match map.get("mimeType") {
+            Some(Value::String(mime_type)) => Some(mime_type.clone()),
+            _ => None,
+        };
+
+        if map.get("$type") == Some(&Value::String("blob".into())) {
+            let cid = match map.get("ref") {
+                Some(Value::Link(cid)) => *cid,
+                _ => return Err("invalid blob ref".into()),
+            };
+            let size = match map.get("size") {
+                Some(Value::Integer(size)) => {
+                    Some(u64::try_from(*size).map_err(|_| "invalid blob ref")?)
+                }
+                _ => None,
+            };
+            return Ok(Some(BlobRef {
+                cid,
+                mime_type,
+                size,
+            }));
+        }
+
+        // legacy blobs only carry the cid as a string and no size
+        match map.get("cid") {
+            Some(Value::String(cid)) if map.len() == 2 && mime_type.is_some() => {
+                Ok(Cid::from_str(cid).ok().map(|cid| BlobRef {
+                    cid,
+                    mime_type,
+                    size: None,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Accepts either a bare cid string or a blob ref object.
+    fn from_js(blob: JsValue) -> Result<BlobRef, JsValue> {
+        if let Some(cid) = blob.as_string() {
+            return Ok(BlobRef {
+                cid: Cid::from_str(&cid).map_err(|_| "couldn't parse given cid")?,
+                mime_type: None,
+                size: None,
+            });
+        }
+        let value = value::decode_cbor(&record_to_cbor(blob, false)?)?;
+        BlobRef::from_value(&value)?.ok_or_else(|| "invalid blob ref".into())
+    }
+
+    /// Checks `bytes` against the cid and, when given, the declared size and
+    /// the `Content-Type` they were served with.
+    pub fn check(&self, bytes: &[u8], content_type: Option<&str>) -> Result<(), JsValue> {
+        if self.cid.codec() != hasher::RAW {
+            return Err("blob cid must use the raw codec".into());
+        }
+        if let Some(size) = self.size {
+            if bytes.len() as u64 != size {
+                return Err("blob size doesn't match declared size".into());
+            }
+        }
+        if hasher::verify(&self.cid, bytes).is_err() {
+            return Err("blob doesn't match its cid".into());
+        }
+        if let (Some(mime_type), Some(content_type)) = (&self.mime_type, content_type) {
+            let content_type = content_type.split(';').next().unwrap_or("").trim();
+            if !content_type.eq_ignore_ascii_case(mime_type) {
+                return Err("blob content type doesn't match declared mimeType".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects every blob ref, current or legacy, in `value`.
+pub fn blob_refs(value: &Value, refs: &mut Vec<BlobRef>) -> Result<(), JsValue> {
+    if let Some(blob) = BlobRef::from_value(value)? {
+        refs.push(blob);
+        return Ok(());
+    }
+    match value {
+        Value::Array(items) => items.iter().try_for_each(|item| blob_refs(item, refs)),
+        Value::Map(map) => map.values().try_for_each(|item| blob_refs(item, refs)),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the body of `resp`, cancelling the download once it grows past
+/// `max_bytes`.
+async fn read_body(resp: &Response, max_bytes: u64, too_large: &str) -> Result<Vec<u8>, JsValue> {
+    if let Ok(Some(length)) = resp.headers().get("content-length") {
+        if length.parse::<u64>().is_ok_and(|length| length > max_bytes) {
+            return Err(too_large.into());
+        }
+    }
+
+    let mut bytes = Vec::new();
+    read_chunks(resp, |chunk| {
+        if bytes.len() as u64 + chunk.len() as u64 > max_bytes {
+            return Err(too_large.into());
+        }
+        bytes.extend_from_slice(chunk);
+        Ok(())
+    })
+    .await?;
+    Ok(bytes)
+}
+
+/// Downloads a blob of `did` from `com.atproto.sync.getBlob` and checks it.
+/// The download is capped at the declared size, or at `max_bytes` for refs
+/// that don't declare one.
+async fn fetch_blob(
+    did: &Did,
+    did_doc: &DidDocument,
+    blob: &BlobRef,
+    max_bytes: u64,
+    fetch_options: &FetchOptions,
+) -> Result<(), JsValue> {
+    if did.as_str() != did_doc.id {
+        return Err("blob did doesn't match did doc id".into());
+    }
+    let url = format!(
+        "{}/xrpc/com.atproto.sync.getBlob?did={}&cid={}",
+        did_doc.get_pds()?,
+        did,
+        blob.cid
+    );
+    let resp = fetch(&url, fetch_options).await?;
+    let content_type = resp.headers().get("content-type").ok().flatten();
+
+    let (max_bytes, too_large) = match blob.size {
+        Some(size) => (size, "blob size doesn't match declared size"),
+        None => (max_bytes, "blob is larger than the size limit"),
+    };
+    let bytes = resp.read(read_body(&resp, max_bytes, too_large)).await?;
+
+    blob.check(&bytes, content_type.as_deref())
+}
+
+/// Verifies a blob of `did`. `blob` is a cid string or a blob ref from a
+/// record (`{$type: "blob", ref, mimeType, size}` or the legacy
+/// `{cid, mimeType}`). If `bytes` is given it is checked directly, otherwise
+/// the blob is fetched from the account's PDS, honouring the network
+/// options and `maxBlobBytes` of `VerifyOptions`.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_blob(
+    did: &str,
+    blob: JsValue,
+    bytes: Option<Vec<u8>>,
+    options: JsValue,
+) -> Result<(), JsValue> {
+    let did = Did::from_str(did)?;
+    let blob = BlobRef::from_js(blob)?;
+
+    if let Some(bytes) = bytes {
+        return blob.check(&bytes, None);
+    }
+
+    let options = VerifyOptions::from_js(options)?;
+    let fetch_options = options.fetch_options()?;
+    let did_doc = get_did_doc(&did, &fetch_options).await?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
+    fetch_blob(
+        &did,
+        &did_doc,
+        &blob,
+        options.max_blob_bytes(),
+        &fetch_options,
+    )
+    .await
+}
+
+/// Fetches and verifies every blob embedded in `record`, which should
+/// already have been authenticated as a record of `did`.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_record_blobs(
+    did: &str,
+    record: JsValue,
+    options: JsValue,
+) -> Result<(), JsValue> {
+    let did = Did::from_str(did)?;
+    let value = value::decode_cbor(&record_to_cbor(record, false)?)?;
+    let mut refs = Vec::new();
+    blob_refs(&value, &mut refs)?;
+    if refs.is_empty() {
+        return Ok(());
+    }
+
+    let options = VerifyOptions::from_js(options)?;
+    let fetch_options = options.fetch_options()?;
+    let did_doc = get_did_doc(&did, &fetch_options).await?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
+    for blob in refs.iter() {
+        fetch_blob(
+            &did,
+            &did_doc,
+            blob,
+            options.max_blob_bytes(),
+            &fetch_options,
+        )
+        .await?;
+    }
+    Ok(())
+}
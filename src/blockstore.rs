@@ -0,0 +1,20 @@
+use cid::Cid;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use std::collections::HashMap;
+use wasm_bindgen::JsValue;
+
+/// Somewhere blocks can be looked up by cid, possibly asynchronously.
+pub trait BlockStore {
+    fn get_block<'a>(&'a self, cid: &'a Cid)
+        -> LocalBoxFuture<'a, Result<Option<Vec<u8>>, JsValue>>;
+}
+
+impl BlockStore for HashMap<Vec<u8>, Vec<u8>> {
+    fn get_block<'a>(
+        &'a self,
+        cid: &'a Cid,
+    ) -> LocalBoxFuture<'a, Result<Option<Vec<u8>>, JsValue>> {
+        let block = self.get(&cid.to_bytes()).cloned();
+        async move { Ok(block) }.boxed_local()
+    }
+}
@@ -0,0 +1,408 @@
+use crate::transport::read_chunks;
+use crate::{hasher, mst, SignedCommitObject};
+use cid::Cid;
+use futures_util::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use wasm_bindgen::prelude::*;
+use web_sys::Response;
+
+#[derive(Deserialize)]
+pub struct CarHeaderV1 {
+    pub version: u64,
+    pub roots: Vec<Cid>,
+}
+
+#[derive(Serialize)]
+struct CarHeader<'a> {
+    roots: &'a [Cid],
+    version: u64,
+}
+
+pub fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+pub fn cid_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() >= 2 && bytes[0] == 0x12 && bytes[1] == 0x20 {
+        return Some(34);
+    }
+    let mut pos = 0;
+    // version, codec and hash function, then the digest length
+    for _ in 0..3 {
+        pos += read_varint(&bytes[pos..])?.1;
+    }
+    let (digest_len, len) = read_varint(&bytes[pos..])?;
+    usize::try_from(digest_len).ok()?.checked_add(pos + len)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Serializes a CAR v1 file into memory, block by block.
+pub struct CarWriter {
+    buffer: Vec<u8>,
+}
+
+impl CarWriter {
+    pub fn new(roots: &[Cid]) -> Result<CarWriter, JsValue> {
+        let header = serde_ipld_dagcbor::to_vec(&CarHeader { roots, version: 1 })
+            .map_err(|_| "couldn't encode car header")?;
+
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, header.len() as u64);
+        buffer.extend_from_slice(&header);
+        Ok(CarWriter { buffer })
+    }
+
+    pub fn write(&mut self, cid: &Cid, data: &[u8]) {
+        let cid = cid.to_bytes();
+        write_varint(&mut self.buffer, (cid.len() + data.len()) as u64);
+        self.buffer.extend_from_slice(&cid);
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub fn write_car(roots: &[Cid], blocks: &[(Cid, &[u8])]) -> Result<Vec<u8>, JsValue> {
+    let mut writer = CarWriter::new(roots)?;
+    for (cid, data) in blocks {
+        writer.write(cid, data);
+    }
+    Ok(writer.finish())
+}
+
+/// Checks that `data` is a dag-cbor repo block hashing to `cid`.
+pub fn check_block(cid: &Cid, data: &[u8]) -> Result<(), JsValue> {
+    hasher::check_codec(cid, hasher::DAG_CBOR)?;
+    if hasher::verify(cid, data).is_err() {
+        return Err("a cid in the car doesn't match its record".into());
+    }
+    Ok(())
+}
+
+/// Reads every block of a CAR file, checking each one against its cid.
+pub async fn read_car(bytes: &[u8]) -> Result<(Vec<Cid>, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
+    let car_reader = iroh_car::CarReader::new(bytes).await;
+    if car_reader.is_err() {
+        return Err("Failed to decode CAR".into());
+    }
+
+    let car_reader = car_reader.unwrap();
+    let mut roots = Vec::new();
+    for root in car_reader.header().roots() {
+        roots.push(Cid::try_from(root.to_bytes().as_slice()).map_err(|_| "invalid root cid")?);
+    }
+    let mut stream = Box::pin(car_reader.stream());
+
+    let mut blocks: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+
+    while let Some(block) = stream.next().await {
+        let (cid, cbor) = block.map_err(|_| "Failed to decode CAR")?;
+        let cid = Cid::try_from(cid.to_bytes().as_slice()).map_err(|_| "invalid block cid")?;
+        check_block(&cid, &cbor)?;
+        blocks.insert(cid.to_bytes(), cbor);
+    }
+
+    Ok((roots, blocks))
+}
+
+pub struct CarLimits {
+    pub max_bytes: u64,
+    pub max_blocks: usize,
+}
+
+impl Default for CarLimits {
+    fn default() -> CarLimits {
+        CarLimits {
+            max_bytes: 8 * 1024 * 1024,
+            max_blocks: 1024,
+        }
+    }
+}
+
+/// Incrementally decodes a CAR v1 file fed in arbitrary chunks, checking
+/// every block as soon as it is complete.
+pub struct CarStreamDecoder {
+    limits: CarLimits,
+    buffer: Vec<u8>,
+    total: u64,
+    roots: Option<Vec<Cid>>,
+    blocks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl CarStreamDecoder {
+    pub fn new(limits: CarLimits) -> CarStreamDecoder {
+        CarStreamDecoder {
+            limits,
+            buffer: Vec::new(),
+            total: 0,
+            roots: None,
+            blocks: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), JsValue> {
+        self.total += chunk.len() as u64;
+        if self.total > self.limits.max_bytes {
+            return Err("car is larger than the size limit".into());
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut pos = 0;
+        loop {
+            let (len, varint_len) = match read_varint(&buffer[pos..]) {
+                Some(varint) => varint,
+                None if buffer.len() - pos >= 10 => return Err("Failed to decode CAR".into()),
+                None => break,
+            };
+            if len == 0 || len > self.limits.max_bytes {
+                return Err("Failed to decode CAR".into());
+            }
+            let end = match usize::try_from(len)
+                .ok()
+                .and_then(|len| (pos + varint_len).checked_add(len))
+            {
+                Some(end) => end,
+                None => return Err("Failed to decode CAR".into()),
+            };
+            if end > buffer.len() {
+                break;
+            }
+            self.section(&buffer[pos + varint_len..end])?;
+            pos = end;
+        }
+        // only the complete sections are dropped, so an unfinished block isn't
+        // copied again for every chunk
+        self.buffer = buffer;
+        self.buffer.drain(..pos);
+
+        Ok(())
+    }
+
+    fn section(&mut self, section: &[u8]) -> Result<(), JsValue> {
+        if self.roots.is_none() {
+            let header: CarHeaderV1 =
+                serde_ipld_dagcbor::from_slice(section).map_err(|_| "Failed to decode CAR")?;
+            if header.version != 1 {
+                return Err("unsupported car version".into());
+            }
+            self.roots = Some(header.roots);
+            return Ok(());
+        }
+
+        if self.blocks.len() >= self.limits.max_blocks {
+            return Err("car has more blocks than the limit".into());
+        }
+        let cid_len = match cid_len(section) {
+            Some(cid_len) if cid_len <= section.len() => cid_len,
+            _ => return Err("Failed to decode CAR".into()),
+        };
+        let cid = Cid::try_from(&section[..cid_len]).map_err(|_| "Failed to decode CAR")?;
+        let data = &section[cid_len..];
+        check_block(&cid, data)?;
+        self.blocks.insert(cid.to_bytes(), data.to_vec());
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(Vec<Cid>, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
+        if !self.buffer.is_empty() {
+            return Err("car ended in the middle of a block".into());
+        }
+        match self.roots {
+            Some(roots) => Ok((roots, self.blocks)),
+            None => Err("Failed to decode CAR".into()),
+        }
+    }
+}
+
+/// Streams the body of `resp` through a `CarStreamDecoder`, cancelling the
+/// download as soon as a block fails to verify or a limit is hit.
+pub async fn read_response(
+    resp: &Response,
+    limits: CarLimits,
+) -> Result<(Vec<Cid>, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
+    if let Ok(Some(length)) = resp.headers().get("content-length") {
+        if length
+            .parse::<u64>()
+            .is_ok_and(|length| length > limits.max_bytes)
+        {
+            return Err("car is larger than the size limit".into());
+        }
+    }
+
+    let mut decoder = CarStreamDecoder::new(limits);
+    read_chunks(resp, |chunk| decoder.push(chunk)).await?;
+    decoder.finish()
+}
+
+/// Picks the commit, the MST nodes on the path to `key` and the record out
+/// of `blocks`, and writes them as a CAR rooted at the commit.
+pub fn extract_proof(
+    blocks: &HashMap<Vec<u8>, Vec<u8>>,
+    commit: Cid,
+    key: &str,
+) -> Result<Vec<u8>, JsValue> {
+    let commit_block = match blocks.get(&commit.to_bytes()) {
+        Some(block) => block,
+        None => return Err("commit block missing from car".into()),
+    };
+    let commit_object: SignedCommitObject =
+        serde_ipld_dagcbor::from_slice(commit_block).map_err(|_| "couldn't decode commit")?;
+
+    let (path, value) = mst::find_path(blocks, commit_object.data, key)?;
+    let value = match value {
+        Some(value) => value,
+        None => return Err("record not found in repo".into()),
+    };
+    let record_block = match blocks.get(&value.to_bytes()) {
+        Some(block) => block,
+        None => return Err("record block missing from car".into()),
+    };
+
+    let mut writer = CarWriter::new(&[commit])?;
+    writer.write(&commit, commit_block);
+    for cid in path {
+        writer.write(&cid, &blocks[&cid.to_bytes()]);
+    }
+    writer.write(&value, record_block);
+    Ok(writer.finish())
+}
+
+/// Cuts the minimal `getRecord`-style proof for `collection/rkey` out of a
+/// full repo export (`com.atproto.sync.getRepo`).
+#[wasm_bindgen]
+pub async fn extract_record_proof(
+    car: Vec<u8>,
+    collection: &str,
+    rkey: &str,
+) -> Result<Vec<u8>, JsValue> {
+    let (roots, blocks) = read_car(&car).await?;
+    let commit = match roots.first() {
+        Some(root) => *root,
+        None => return Err("car has no root".into()),
+    };
+    extract_proof(&blocks, commit, &crate::repo::record_key(collection, rkey)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cid_for_cbor;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn test_car() -> (Cid, Vec<(Cid, Vec<u8>)>, Vec<u8>) {
+        let blocks: Vec<(Cid, Vec<u8>)> = [vec![], vec![7; 200], vec![1; 5000]]
+            .iter()
+            .map(|bytes| {
+                let block = serde_ipld_dagcbor::to_vec(&serde_bytes::Bytes::new(bytes)).unwrap();
+                (cid_for_cbor(&block), block)
+            })
+            .collect();
+        let parts: Vec<(Cid, &[u8])> = blocks
+            .iter()
+            .map(|(cid, block)| (*cid, &block[..]))
+            .collect();
+        let car = write_car(&[blocks[0].0], &parts).unwrap();
+        (blocks[0].0, blocks, car)
+    }
+
+    fn decode(
+        chunks: &[&[u8]],
+        limits: CarLimits,
+    ) -> Result<(Vec<Cid>, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
+        let mut decoder = CarStreamDecoder::new(limits);
+        for chunk in chunks {
+            decoder.push(chunk)?;
+        }
+        decoder.finish()
+    }
+
+    #[wasm_bindgen_test]
+    fn decodes_any_chunking() {
+        let (root, blocks, car) = test_car();
+        let expected: HashMap<Vec<u8>, Vec<u8>> = blocks
+            .into_iter()
+            .map(|(cid, block)| (cid.to_bytes(), block))
+            .collect();
+
+        let bytes: Vec<&[u8]> = car.chunks(1).collect();
+        let (roots, decoded) = decode(&bytes, CarLimits::default()).unwrap();
+        assert_eq!(roots, [root]);
+        assert_eq!(decoded, expected);
+
+        // splits at pseudo-random points, which land inside varints, cids and
+        // blocks alike
+        let mut seed = 1u32;
+        for _ in 0..20 {
+            let mut chunks = Vec::new();
+            let mut rest = &car[..];
+            while !rest.is_empty() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let (chunk, tail) = rest.split_at((seed >> 16) as usize % 300 % rest.len() + 1);
+                chunks.push(chunk);
+                rest = tail;
+            }
+            let (roots, decoded) = decode(&chunks, CarLimits::default()).unwrap();
+            assert_eq!(roots, [root]);
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn enforces_limits_while_streaming() {
+        let (_, _, car) = test_car();
+        let bytes: Vec<&[u8]> = car.chunks(1).collect();
+
+        let limits = CarLimits {
+            max_bytes: car.len() as u64 - 1,
+            ..CarLimits::default()
+        };
+        let err = decode(&bytes, limits).unwrap_err();
+        assert_eq!(err, "car is larger than the size limit");
+
+        let limits = CarLimits {
+            max_blocks: 2,
+            ..CarLimits::default()
+        };
+        let err = decode(&bytes, limits).unwrap_err();
+        assert_eq!(err, "car has more blocks than the limit");
+
+        let err = decode(&bytes[..bytes.len() - 1], CarLimits::default()).unwrap_err();
+        assert_eq!(err, "car ended in the middle of a block");
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_overflowing_lengths() {
+        assert_eq!(
+            cid_len(&[1, 0x71, 0x12, 0xff, 0xff, 0xff, 0xff, 0x0f]),
+            None
+        );
+
+        let mut car = CarWriter::new(&[]).unwrap().finish();
+        car.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+        let limits = CarLimits {
+            max_bytes: u64::MAX,
+            ..CarLimits::default()
+        };
+        let err = decode(&[&car], limits).unwrap_err();
+        assert_eq!(err, "Failed to decode CAR");
+    }
+}
@@ -0,0 +1,65 @@
+use cid::Cid;
+use sha2::Digest;
+use wasm_bindgen::JsValue;
+
+pub const DAG_CBOR: u64 = 0x71;
+pub const RAW: u64 = 0x55;
+
+pub const SHA2_256: u64 = 0x12;
+
+pub struct Hasher {
+    pub code: u64,
+    pub digest_len: usize,
+    pub hash: fn(&[u8]) -> Vec<u8>,
+}
+
+fn sha2_256(data: &[u8]) -> Vec<u8> {
+    sha2::Sha256::digest(data).to_vec()
+}
+
+/// Every multihash function a cid may use. Supporting a new algorithm only
+/// takes a row here.
+pub const HASHERS: &[Hasher] = &[Hasher {
+    code: SHA2_256,
+    digest_len: 32,
+    hash: sha2_256,
+}];
+
+pub fn find(code: u64) -> Option<&'static Hasher> {
+    HASHERS.iter().find(|hasher| hasher.code == code)
+}
+
+/// Checks that `data` hashes to the multihash in `cid`.
+pub fn verify(cid: &Cid, data: &[u8]) -> Result<(), JsValue> {
+    let hasher = match find(cid.hash().code()) {
+        Some(hasher) => hasher,
+        None => return Err("unsupported cid hash function".into()),
+    };
+    if cid.hash().size() as usize != hasher.digest_len {
+        return Err("unexpected cid hash length".into());
+    }
+    if cid.hash().digest() != (hasher.hash)(data).as_slice() {
+        return Err("cid doesn't match its data".into());
+    }
+    Ok(())
+}
+
+pub fn check_codec(cid: &Cid, codec: u64) -> Result<(), JsValue> {
+    if cid.codec() != codec {
+        return Err(match codec {
+            DAG_CBOR => "expected a dag-cbor cid",
+            RAW => "expected a raw cid",
+            _ => "unexpected cid codec",
+        }
+        .into());
+    }
+    Ok(())
+}
+
+pub fn cid_for(codec: u64, data: &[u8]) -> Cid {
+    let digest = sha2_256(data);
+    Cid::new_v1(
+        codec,
+        cid::multihash::Multihash::wrap(SHA2_256, &digest).expect("sha256 digest fits in a multihash"),
+    )
+}
@@ -0,0 +1,513 @@
+use crate::blockstore::BlockStore;
+use crate::car::{check_block, cid_len, read_varint, CarHeaderV1};
+use crate::value;
+use crate::{
+    check_record_cid, check_record_type, key_curve, mst, rev_store, split_record_uri,
+    value_record_type, verify_commit, DidDocument, VerificationReport, VerifyOptions,
+};
+use cid::Cid;
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::Uint8Array;
+
+const V2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+const V2_HEADER_END: u64 = 51;
+
+const INDEX_SORTED: u64 = 0x0400;
+const MULTIHASH_INDEX_SORTED: u64 = 0x0401;
+
+/// A byte source that can be read at arbitrary offsets.
+pub trait RandomAccess {
+    fn size(&self) -> u64;
+    fn read_at(&self, offset: u64, len: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, JsValue>>;
+}
+
+impl RandomAccess for Vec<u8> {
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, len: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, JsValue>> {
+        let clamp = |offset: u64| usize::try_from(offset).map_or(self.len(), |o| o.min(self.len()));
+        let start = clamp(offset);
+        let end = clamp(offset.saturating_add(len));
+        let bytes = self[start..end].to_vec();
+        async move { Ok(bytes) }.boxed_local()
+    }
+}
+
+/// Reads slices of a `Blob` (or `File`) without loading the whole thing.
+pub struct BlobSource(pub web_sys::Blob);
+
+impl RandomAccess for BlobSource {
+    fn size(&self) -> u64 {
+        self.0.size() as u64
+    }
+
+    fn read_at(&self, offset: u64, len: u64) -> LocalBoxFuture<'_, Result<Vec<u8>, JsValue>> {
+        let slice = self
+            .0
+            .slice_with_f64_and_f64(offset as f64, offset.saturating_add(len) as f64);
+        async move {
+            let array_buffer = JsFuture::from(slice?.array_buffer()).await?;
+            Ok(Uint8Array::new(&array_buffer).to_vec())
+        }
+        .boxed_local()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], JsValue> {
+        if self.bytes.len() - self.pos < len {
+            return Err("car index is truncated".into());
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, JsValue> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, JsValue> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn read_width_buckets(
+    reader: &mut Reader,
+    index: &mut HashMap<Vec<u8>, u64>,
+) -> Result<(), JsValue> {
+    let buckets = reader.u32()?;
+    for _ in 0..buckets {
+        let width = reader.u32()? as usize;
+        let len = usize::try_from(reader.u64()?).map_err(|_| "car index is truncated")?;
+        if width <= 8 || len % width != 0 {
+            return Err("invalid car index bucket".into());
+        }
+        let entries = reader.take(len)?;
+        for entry in entries.chunks(width) {
+            let (digest, offset) = entry.split_at(width - 8);
+            index.insert(
+                digest.to_vec(),
+                u64::from_le_bytes(offset.try_into().unwrap()),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parses an `IndexSorted` or `MultihashIndexSorted` CARv2 index into a map
+/// from multihash digest to section offset.
+fn parse_index(bytes: &[u8]) -> Result<HashMap<Vec<u8>, u64>, JsValue> {
+    let (codec, len) = read_varint(bytes).ok_or("invalid car index")?;
+    let mut reader = Reader { bytes, pos: len };
+    let mut index = HashMap::new();
+
+    match codec {
+        INDEX_SORTED => read_width_buckets(&mut reader, &mut index)?,
+        MULTIHASH_INDEX_SORTED => {
+            let codes = reader.u32()?;
+            for _ in 0..codes {
+                reader.u64()?;
+                read_width_buckets(&mut reader, &mut index)?;
+            }
+        }
+        _ => return Err("unsupported car index".into()),
+    }
+
+    Ok(index)
+}
+
+/// A CAR file (v1 or v2) read through an index, so that only the blocks that
+/// are asked for are ever loaded.
+pub struct IndexedCar<S> {
+    source: S,
+    data_offset: u64,
+    roots: Vec<Cid>,
+    index: HashMap<Vec<u8>, u64>,
+}
+
+impl<S: RandomAccess> IndexedCar<S> {
+    pub async fn open(source: S) -> Result<IndexedCar<S>, JsValue> {
+        let head = source.read_at(0, V2_HEADER_END).await?;
+
+        let (data_offset, data_end, index_offset) =
+            if head.len() as u64 == V2_HEADER_END && head[..11] == V2_PRAGMA {
+                let data_offset = u64::from_le_bytes(head[27..35].try_into().unwrap());
+                let data_size = u64::from_le_bytes(head[35..43].try_into().unwrap());
+                let index_offset = u64::from_le_bytes(head[43..51].try_into().unwrap());
+                (
+                    data_offset,
+                    data_offset.saturating_add(data_size),
+                    index_offset,
+                )
+            } else {
+                (0, source.size(), 0)
+            };
+
+        let head = source.read_at(data_offset, 10).await?;
+        let (header_len, varint_len) = read_varint(&head).ok_or("Failed to decode CAR")?;
+        let header_offset = data_offset
+            .checked_add(varint_len as u64)
+            .ok_or("Failed to decode CAR")?;
+        let header = source.read_at(header_offset, header_len).await?;
+        let header: CarHeaderV1 =
+            serde_ipld_dagcbor::from_slice(&header).map_err(|_| "Failed to decode CAR")?;
+        if header.version != 1 {
+            return Err("unsupported car version".into());
+        }
+
+        let mut car = IndexedCar {
+            source,
+            data_offset,
+            roots: header.roots,
+            index: HashMap::new(),
+        };
+
+        if index_offset != 0 {
+            let size = car.source.size().saturating_sub(index_offset);
+            let index = car.source.read_at(index_offset, size).await?;
+            car.index = parse_index(&index)?;
+        } else {
+            let first = header_len
+                .checked_add(varint_len as u64)
+                .ok_or("Failed to decode CAR")?;
+            car.scan(first, data_end).await?;
+        }
+
+        Ok(car)
+    }
+
+    /// Builds the index by hopping from section header to section header.
+    /// Lengths come from the file, so every sum is checked.
+    async fn scan(&mut self, mut offset: u64, data_end: u64) -> Result<(), JsValue> {
+        loop {
+            let at = match self.data_offset.checked_add(offset) {
+                Some(at) if at < data_end => at,
+                Some(_) => break,
+                None => return Err("Failed to decode CAR".into()),
+            };
+            let head = self.source.read_at(at, 80).await?;
+            let (len, varint_len) = read_varint(&head).ok_or("Failed to decode CAR")?;
+            if len == 0 {
+                break;
+            }
+            let cid_len = cid_len(&head[varint_len..]).ok_or("Failed to decode CAR")?;
+            if varint_len + cid_len > head.len() {
+                return Err("Failed to decode CAR".into());
+            }
+            let cid = Cid::try_from(&head[varint_len..varint_len + cid_len])
+                .map_err(|_| "Failed to decode CAR")?;
+            self.index.insert(cid.hash().digest().to_vec(), offset);
+            offset = offset
+                .checked_add(varint_len as u64)
+                .and_then(|offset| offset.checked_add(len))
+                .ok_or("Failed to decode CAR")?;
+        }
+        Ok(())
+    }
+
+    pub fn roots(&self) -> &[Cid] {
+        &self.roots
+    }
+
+    async fn read_block(&self, cid: &Cid) -> Result<Option<Vec<u8>>, JsValue> {
+        let offset = match self.index.get(cid.hash().digest()) {
+            Some(offset) => self
+                .data_offset
+                .checked_add(*offset)
+                .ok_or("Failed to decode CAR")?,
+            None => return Ok(None),
+        };
+
+        let head = self.source.read_at(offset, 10).await?;
+        let (len, varint_len) = read_varint(&head).ok_or("Failed to decode CAR")?;
+        let section_offset = offset
+            .checked_add(varint_len as u64)
+            .ok_or("Failed to decode CAR")?;
+        let section = self.source.read_at(section_offset, len).await?;
+        let cid_len = cid_len(&section).ok_or("Failed to decode CAR")?;
+        if cid_len > section.len() || section[..cid_len] != cid.to_bytes()[..] {
+            return Err("car index points at the wrong block".into());
+        }
+
+        let data = section[cid_len..].to_vec();
+        check_block(cid, &data)?;
+
+        Ok(Some(data))
+    }
+}
+
+impl<S: RandomAccess> BlockStore for IndexedCar<S> {
+    fn get_block<'a>(
+        &'a self,
+        cid: &'a Cid,
+    ) -> LocalBoxFuture<'a, Result<Option<Vec<u8>>, JsValue>> {
+        self.read_block(cid).boxed_local()
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_ARCHIVE: &str = r#"
+export function verify_record_in_archive(archive: Blob, uri: string, cid: string, did_doc: DidDocument, options?: VerifyOptions): Promise<VerificationReport>;
+"#;
+
+/// Verifies that `uri` points at `cid` in the signed repo archived in
+/// `archive` (a CAR v1 or v2 `Blob`), reading only the commit, the MST path
+/// and the record. Returns the same report as `verify_record_with_doc`,
+/// without a `pds` since none was contacted.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_record_in_archive(
+    archive: web_sys::Blob,
+    uri: &str,
+    cid: &str,
+    did_doc: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let options = VerifyOptions::from_js(options)?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
+    let cid = Cid::from_str(cid).map_err(|_| "couldn't parse given cid")?;
+    check_record_cid(&cid)?;
+
+    let (did, collection, rkey) = split_record_uri(uri)?;
+    if did.as_str() != did_doc.id {
+        return Err("record uri did doesn't match did doc id".into());
+    }
+
+    let car = IndexedCar::open(BlobSource(archive)).await?;
+    let root = match car.roots().first() {
+        Some(root) => *root,
+        None => return Err("car has no root".into()),
+    };
+    let commit_block = match car.get_block(&root).await? {
+        Some(block) => block,
+        None => return Err("commit block missing from car".into()),
+    };
+
+    let multibase_key = did_doc.get_signing_key()?;
+    let (_, signing_key) =
+        libipld::multibase::decode(multibase_key).map_err(|_| "couldn't decode signing key")?;
+    let commit = verify_commit(&commit_block, did.as_str(), &signing_key)?;
+    options.check_cbor("commit", &commit_block)?;
+    options.check_rev(&commit.rev)?;
+
+    let (path, found) = mst::lookup(&car, commit.data, &format!("{}/{}", collection, rkey)).await?;
+    match found {
+        Some(found) if found == cid => {}
+        Some(_) => return Err("record in repo has a different cid".into()),
+        None => return Err("could not find record in signed repo".into()),
+    }
+
+    // read even without `returnRecord`, to check its `$type`
+    let record = match car.get_block(&cid).await? {
+        Some(block) => {
+            options.check_cbor("record", &block)?;
+            value::decode_cbor(&block)?
+        }
+        None => return Err("record block missing from car".into()),
+    };
+    check_record_type(&collection, value_record_type(&record))?;
+
+    rev_store::check_and_record(&did, &commit.rev, &root.to_string()).await?;
+
+    value::serialize_js(&VerificationReport {
+        did: did.as_str(),
+        pds: None,
+        signing_key: multibase_key,
+        curve: key_curve(&signing_key),
+        commit_cid: root.to_string(),
+        rev: commit.rev,
+        data: commit.data.to_string(),
+        record_depth: path.len() as u32 - 1,
+        blocks_checked: 2 + path.len(),
+        did_doc_source: "provided",
+        active: None,
+        status: None,
+        latest_commit: None,
+        is_latest_commit: None,
+        record: options.return_record.then(|| value::Json(&record)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::write_car;
+    use crate::cid_for_cbor;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn blocks() -> Vec<(Cid, Vec<u8>)> {
+        ["commit", "node", "record"]
+            .iter()
+            .map(|name| {
+                let block = serde_ipld_dagcbor::to_vec(name).unwrap();
+                (cid_for_cbor(&block), block)
+            })
+            .collect()
+    }
+
+    fn car_v1(blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+        let parts: Vec<(Cid, &[u8])> = blocks
+            .iter()
+            .map(|(cid, block)| (*cid, &block[..]))
+            .collect();
+        write_car(&[blocks[0].0], &parts).unwrap()
+    }
+
+    /// The body of an `IndexSorted` index over `index`, in one bucket.
+    fn sorted_buckets(index: &HashMap<Vec<u8>, u64>) -> Vec<u8> {
+        let mut entries: Vec<_> = index.iter().collect();
+        entries.sort();
+        let width = entries[0].0.len() + 8;
+        let mut out = Vec::new();
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&(width as u32).to_le_bytes());
+        out.extend_from_slice(&((entries.len() * width) as u64).to_le_bytes());
+        for (digest, offset) in entries {
+            out.extend_from_slice(digest);
+            out.extend_from_slice(&offset.to_le_bytes());
+        }
+        out
+    }
+
+    /// Offsets of the sections of `v1`, from scanning it.
+    async fn offsets(v1: &[u8]) -> HashMap<Vec<u8>, u64> {
+        IndexedCar::open(v1.to_vec()).await.unwrap().index
+    }
+
+    async fn index_sorted(v1: &[u8]) -> Vec<u8> {
+        let mut index = vec![0x80, 0x08];
+        index.extend(sorted_buckets(&offsets(v1).await));
+        index
+    }
+
+    async fn multihash_index_sorted(v1: &[u8]) -> Vec<u8> {
+        let mut index = vec![0x81, 0x08];
+        index.extend_from_slice(&1u32.to_le_bytes());
+        index.extend_from_slice(&0x12u64.to_le_bytes());
+        index.extend(sorted_buckets(&offsets(v1).await));
+        index
+    }
+
+    /// Wraps `v1` in a CARv2 with `index` after the data, if given.
+    fn car_v2(v1: &[u8], index: Option<&[u8]>) -> Vec<u8> {
+        let mut car = V2_PRAGMA.to_vec();
+        car.extend_from_slice(&[0; 16]);
+        car.extend_from_slice(&V2_HEADER_END.to_le_bytes());
+        car.extend_from_slice(&(v1.len() as u64).to_le_bytes());
+        let index_offset = match index {
+            Some(_) => V2_HEADER_END + v1.len() as u64,
+            None => 0,
+        };
+        car.extend_from_slice(&index_offset.to_le_bytes());
+        car.extend_from_slice(v1);
+        car.extend_from_slice(index.unwrap_or(&[]));
+        car
+    }
+
+    async fn check_reads(car: Vec<u8>, blocks: &[(Cid, Vec<u8>)]) {
+        let car = IndexedCar::open(car).await.unwrap();
+        assert_eq!(car.roots(), [blocks[0].0]);
+        for (cid, block) in blocks {
+            assert_eq!(car.get_block(cid).await.unwrap().as_ref(), Some(block));
+        }
+        let missing = cid_for_cbor(&serde_ipld_dagcbor::to_vec("missing").unwrap());
+        assert_eq!(car.get_block(&missing).await.unwrap(), None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn reads_v1_and_v2() {
+        let blocks = blocks();
+        let v1 = car_v1(&blocks);
+
+        check_reads(v1.clone(), &blocks).await;
+        check_reads(car_v2(&v1, None), &blocks).await;
+        check_reads(car_v2(&v1, Some(&index_sorted(&v1).await)), &blocks).await;
+        check_reads(
+            car_v2(&v1, Some(&multihash_index_sorted(&v1).await)),
+            &blocks,
+        )
+        .await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn only_reads_indexed_blocks() {
+        let blocks = blocks();
+        let v1 = car_v1(&blocks);
+        let mut index = offsets(&v1).await;
+        index.remove(blocks[2].0.hash().digest());
+        let mut sorted = vec![0x80, 0x08];
+        sorted.extend(sorted_buckets(&index));
+
+        let car = IndexedCar::open(car_v2(&v1, Some(&sorted))).await.unwrap();
+        assert!(car.get_block(&blocks[1].0).await.unwrap().is_some());
+        assert_eq!(car.get_block(&blocks[2].0).await.unwrap(), None);
+    }
+
+    async fn open_err(car: Vec<u8>) -> JsValue {
+        IndexedCar::open(car).await.err().unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_bad_indexes() {
+        let blocks = blocks();
+        let v1 = car_v1(&blocks);
+        let index = index_sorted(&v1).await;
+
+        let truncated = car_v2(&v1, Some(&index[..index.len() - 1]));
+        assert_eq!(open_err(truncated).await, "car index is truncated");
+
+        let mut unknown = index.clone();
+        unknown[0] = 0x82;
+        let unknown = car_v2(&v1, Some(&unknown));
+        assert_eq!(open_err(unknown).await, "unsupported car index");
+
+        let mut narrow = index.clone();
+        narrow[6..10].copy_from_slice(&8u32.to_le_bytes());
+        let narrow = car_v2(&v1, Some(&narrow));
+        assert_eq!(open_err(narrow).await, "invalid car index bucket");
+
+        // every entry pointing at the first block
+        let mut entries = offsets(&v1).await;
+        let first = entries[blocks[0].0.hash().digest()];
+        entries.values_mut().for_each(|offset| *offset = first);
+        let mut wrong = vec![0x80, 0x08];
+        wrong.extend(sorted_buckets(&entries));
+        let car = IndexedCar::open(car_v2(&v1, Some(&wrong))).await.unwrap();
+        let err = car.get_block(&blocks[1].0).await.unwrap_err();
+        assert_eq!(err, "car index points at the wrong block");
+
+        // an offset that overflows once the data offset is added
+        entries.values_mut().for_each(|offset| *offset = u64::MAX);
+        let mut overflowing = vec![0x80, 0x08];
+        overflowing.extend(sorted_buckets(&entries));
+        let car = IndexedCar::open(car_v2(&v1, Some(&overflowing)))
+            .await
+            .unwrap();
+        let err = car.get_block(&blocks[1].0).await.unwrap_err();
+        assert_eq!(err, "Failed to decode CAR");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_overflowing_sections() {
+        let blocks = blocks();
+        let mut v1 = car_v1(&blocks[..1]);
+        // a section claiming nearly 2^64 bytes, followed by its cid
+        v1.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        v1.extend_from_slice(&blocks[1].0.to_bytes());
+        assert_eq!(open_err(v1).await, "Failed to decode CAR");
+    }
+}
@@ -4,19 +4,82 @@
 
 extern crate serde;
 
+use crate::value::decode_base64;
 use serde::ser::{self, Serialize, SerializeMap, SerializeSeq};
 use serde::{de, serde_if_integer128};
 use std::cell::RefCell;
 use std::fmt;
 use std::str::FromStr;
 
+const MAX_SAFE_INTEGER: i64 = 9007199254740991;
+
 /// Transcodes from a Serde `Deserializer` to a Serde `Serializer`.
 pub fn transcode<'de, D, S>(d: D, s: S) -> Result<S::Ok, S::Error>
 where
     D: de::Deserializer<'de>,
     S: ser::Serializer,
 {
-    Transcoder::new(d).serialize(s)
+    Transcoder::new(d, &Context::default()).serialize(s)
+}
+
+/// Like `transcode`, but only lets values of the atproto data model through:
+/// no floats, no integers outside the JavaScript safe range and no non-string
+/// map keys. Errors name the path of the offending value.
+pub fn transcode_strict<'de, D, S>(d: D, s: S) -> Result<S::Ok, S::Error>
+where
+    D: de::Deserializer<'de>,
+    S: ser::Serializer,
+{
+    let context = Context {
+        strict: true,
+        ..Context::default()
+    };
+    Transcoder::new(d, &context).serialize(s)
+}
+
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Renders a path like `facets[0].index.byteEnd`.
+pub fn format_path(path: &[Segment]) -> String {
+    if path.is_empty() {
+        return "(root)".into();
+    }
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(key);
+            }
+            Segment::Index(index) => out.push_str(&format!("[{}]", index)),
+        }
+    }
+    out
+}
+
+/// State shared by every level of a transcoding.
+#[derive(Default)]
+pub struct Context {
+    strict: bool,
+    path: RefCell<Vec<Segment>>,
+}
+
+impl Context {
+    fn error<E: de::Error>(&self, msg: &str) -> E {
+        E::custom(format!("{}: {}", format_path(&self.path.borrow()), msg))
+    }
+
+    fn check_integer<E: de::Error>(&self, in_range: bool) -> Result<(), E> {
+        if self.strict && !in_range {
+            return Err(self.error("integer is outside the safe range"));
+        }
+        Ok(())
+    }
 }
 
 /// A Serde transcoder.
@@ -29,19 +92,19 @@ where
 /// Unlike traditional serializable types, `Transcoder`'s `Serialize`
 /// implementation is *not* idempotent, as it advances the state of its
 /// internal `Deserializer`. It should only ever be serialized once.
-pub struct Transcoder<D>(RefCell<Option<D>>);
+pub struct Transcoder<'c, D>(RefCell<Option<D>>, &'c Context);
 
-impl<'de, D> Transcoder<D>
+impl<'de, 'c, D> Transcoder<'c, D>
 where
     D: de::Deserializer<'de>,
 {
     /// Constructs a new `Transcoder`.
-    pub fn new(d: D) -> Transcoder<D> {
-        Transcoder(RefCell::new(Some(d)))
+    pub fn new(d: D, context: &'c Context) -> Transcoder<'c, D> {
+        Transcoder(RefCell::new(Some(d)), context)
     }
 }
 
-impl<'de, D> ser::Serialize for Transcoder<D>
+impl<'de, 'c, D> ser::Serialize for Transcoder<'c, D>
 where
     D: de::Deserializer<'de>,
 {
@@ -53,14 +116,14 @@ where
             .borrow_mut()
             .take()
             .expect("Transcoder may only be serialized once")
-            .deserialize_any(Visitor(s))
+            .deserialize_any(Visitor(s, self.1))
             .map_err(d2s)
     }
 }
 
-struct Visitor<S>(S);
+struct Visitor<'c, S>(S, &'c Context);
 
-impl<'de, S> de::Visitor<'de> for Visitor<S>
+impl<'de, 'c, S> de::Visitor<'de> for Visitor<'c, S>
 where
     S: ser::Serializer,
 {
@@ -102,6 +165,7 @@ where
     where
         E: de::Error,
     {
+        self.1.check_integer((-MAX_SAFE_INTEGER..=MAX_SAFE_INTEGER).contains(&v))?;
         self.0.serialize_i64(v).map_err(s2d)
     }
 
@@ -130,6 +194,7 @@ where
     where
         E: de::Error,
     {
+        self.1.check_integer(v <= MAX_SAFE_INTEGER as u64)?;
         self.0.serialize_u64(v).map_err(s2d)
     }
 
@@ -137,12 +202,14 @@ where
         fn visit_i128<E>(self, v: i128) -> Result<S::Ok, E>
             where E: de::Error
         {
+            self.1.check_integer((-(MAX_SAFE_INTEGER as i128)..=MAX_SAFE_INTEGER as i128).contains(&v))?;
             self.0.serialize_i128(v).map_err(s2d)
         }
 
         fn visit_u128<E>(self, v: u128) -> Result<S::Ok, E>
             where E: de::Error
         {
+            self.1.check_integer(v <= MAX_SAFE_INTEGER as u128)?;
             self.0.serialize_u128(v).map_err(s2d)
         }
     }
@@ -151,13 +218,21 @@ where
     where
         E: de::Error,
     {
-        self.0.serialize_f32(v).map_err(s2d)
+        de::Visitor::visit_f64(self, v as f64)
     }
 
     fn visit_f64<E>(self, v: f64) -> Result<S::Ok, E>
     where
         E: de::Error,
     {
+        if self.1.strict {
+            // integral numbers within the safe range arrive through visit_i64
+            return Err(self.1.error(if v.fract() == 0.0 {
+                "integer is outside the safe range"
+            } else {
+                "floats are not allowed"
+            }));
+        }
         self.0.serialize_f64(v).map_err(s2d)
     }
 
@@ -207,7 +282,9 @@ where
     where
         D: de::Deserializer<'de>,
     {
-        self.0.serialize_some(&Transcoder::new(d)).map_err(s2d)
+        self.0
+            .serialize_some(&Transcoder::new(d, self.1))
+            .map_err(s2d)
     }
 
     fn visit_unit<E>(self) -> Result<S::Ok, E>
@@ -222,7 +299,7 @@ where
         D: de::Deserializer<'de>,
     {
         self.0
-            .serialize_newtype_struct("<unknown>", &Transcoder::new(d))
+            .serialize_newtype_struct("<unknown>", &Transcoder::new(d, self.1))
             .map_err(s2d)
     }
 
@@ -231,7 +308,16 @@ where
         V: de::SeqAccess<'de>,
     {
         let mut s = self.0.serialize_seq(v.size_hint()).map_err(s2d)?;
-        while let Some(()) = v.next_element_seed(SeqSeed(&mut s))? {}
+        let mut index = 0;
+        loop {
+            self.1.path.borrow_mut().push(Segment::Index(index));
+            let element = v.next_element_seed(SeqSeed(&mut s, self.1))?;
+            self.1.path.borrow_mut().pop();
+            if element.is_none() {
+                break;
+            }
+            index += 1;
+        }
         s.end().map_err(s2d)
     }
 
@@ -239,7 +325,8 @@ where
     where
         V: de::MapAccess<'de>,
     {
-        let next_key = match v.next_key::<String>()? {
+        let context = self.1;
+        let next_key = match read_key(&mut v, context)? {
             Some(key) => key,
             None => {
                 let s = self.0.serialize_map(v.size_hint()).map_err(s2d)?;
@@ -249,8 +336,9 @@ where
 
         if next_key == "$link" {
             let next_value = v.next_value::<String>()?;
+            expect_no_more_keys(&mut v, "$link")?;
             let cid = cid::Cid::from_str(next_value.as_str())
-                .expect("$link must have valid cid")
+                .map_err(|_| context.error("$link must have valid cid"))?
                 .to_bytes();
             return self
                 .0
@@ -261,41 +349,56 @@ where
                 .map_err(s2d);
         }
 
-        let mut s = self.0.serialize_map(v.size_hint()).map_err(s2d)?;
-        s.serialize_key(&next_key).map_err(s2d)?;
-        v.next_value_seed(ValueSeed(&mut s))?;
+        if next_key == "$bytes" {
+            let next_value = v.next_value::<String>()?;
+            expect_no_more_keys(&mut v, "$bytes")?;
+            let bytes = decode_base64(&next_value)
+                .ok_or_else(|| context.error("$bytes must be valid base64"))?;
+            return self.0.serialize_bytes(&bytes).map_err(s2d);
+        }
 
-        while let Some(()) = v.next_key_seed(KeySeed(&mut s))? {
-            v.next_value_seed(ValueSeed(&mut s))?;
+        let mut s = self.0.serialize_map(v.size_hint()).map_err(s2d)?;
+        let mut key = Some(next_key);
+        while let Some(next_key) = key {
+            s.serialize_key(&next_key).map_err(s2d)?;
+            context.path.borrow_mut().push(Segment::Key(next_key));
+            v.next_value_seed(ValueSeed(&mut s, context))?;
+            context.path.borrow_mut().pop();
+            key = read_key(&mut v, context)?;
         }
 
         s.end().map_err(s2d)
     }
 }
 
-struct SeqSeed<'a, S: 'a>(&'a mut S);
-
-impl<'de, 'a, S> de::DeserializeSeed<'de> for SeqSeed<'a, S>
+fn read_key<'de, V>(v: &mut V, context: &Context) -> Result<Option<String>, V::Error>
 where
-    S: ser::SerializeSeq,
+    V: de::MapAccess<'de>,
 {
-    type Value = ();
+    v.next_key::<String>()
+        .map_err(|_| context.error("map keys must be strings"))
+}
 
-    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
-    where
-        D: de::Deserializer<'de>,
-    {
-        self.0
-            .serialize_element(&Transcoder::new(deserializer))
-            .map_err(s2d)
+/// `$link` and `$bytes` objects stand for a single value, so they can't carry
+/// any other fields.
+fn expect_no_more_keys<'de, V>(v: &mut V, key: &str) -> Result<(), V::Error>
+where
+    V: de::MapAccess<'de>,
+{
+    match v.next_key::<String>()? {
+        Some(other) => Err(de::Error::custom(format!(
+            "{} object must not have other keys, found {}",
+            key, other
+        ))),
+        None => Ok(()),
     }
 }
 
-struct KeySeed<'a, S: 'a>(&'a mut S);
+struct SeqSeed<'a, 'c, S: 'a>(&'a mut S, &'c Context);
 
-impl<'de, 'a, S> de::DeserializeSeed<'de> for KeySeed<'a, S>
+impl<'de, 'a, 'c, S> de::DeserializeSeed<'de> for SeqSeed<'a, 'c, S>
 where
-    S: ser::SerializeMap,
+    S: ser::SerializeSeq,
 {
     type Value = ();
 
@@ -304,14 +407,14 @@ where
         D: de::Deserializer<'de>,
     {
         self.0
-            .serialize_key(&Transcoder::new(deserializer))
+            .serialize_element(&Transcoder::new(deserializer, self.1))
             .map_err(s2d)
     }
 }
 
-struct ValueSeed<'a, S: 'a>(&'a mut S);
+struct ValueSeed<'a, 'c, S: 'a>(&'a mut S, &'c Context);
 
-impl<'de, 'a, S> de::DeserializeSeed<'de> for ValueSeed<'a, S>
+impl<'de, 'a, 'c, S> de::DeserializeSeed<'de> for ValueSeed<'a, 'c, S>
 where
     S: ser::SerializeMap,
 {
@@ -322,7 +425,7 @@ where
         D: de::Deserializer<'de>,
     {
         self.0
-            .serialize_value(&Transcoder::new(deserializer))
+            .serialize_value(&Transcoder::new(deserializer, self.1))
             .map_err(s2d)
     }
 }
@@ -342,3 +445,116 @@ where
 {
     D::custom(s.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{cid_for_cbor, record_to_cbor};
+    use wasm_bindgen::JsValue;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys::js_sys::{Reflect, JSON};
+
+    fn cid_of(json: &str) -> Result<String, JsValue> {
+        let record = JSON::parse(json)?;
+        Ok(cid_for_cbor(&record_to_cbor(record, false)?).to_string())
+    }
+
+    fn strict_error_of(record: JsValue) -> String {
+        record_to_cbor(record, true)
+            .unwrap_err()
+            .as_string()
+            .unwrap()
+    }
+
+    fn strict_error(json: &str) -> String {
+        strict_error_of(JSON::parse(json).unwrap())
+    }
+
+    #[wasm_bindgen_test]
+    fn data_model_fixture() {
+        let json = r#"{
+            "string": "abc",
+            "unicode": "a~öñ©⽘☎𓋓😀👨‍👩‍👧‍👧",
+            "integer": 123,
+            "bool": true,
+            "null": null,
+            "array": ["abc", "def", "ghi"],
+            "object": {"string": "abc", "number": 123, "bool": true, "arr": ["abc", "def", "ghi"]},
+            "link": {"$link": "bafyreidwmohm4mqkxcqc2cixys5kvwwirt2qa6456r7iznoaao473lu4m4"},
+            "bytes": {"$bytes": "nFERjvLLiw9qm45JrqH9QTzyC2Lu1Xb4ne6+sBrCzI0"}
+        }"#;
+        assert_eq!(
+            cid_of(json).unwrap(),
+            "bafyreifkj3q6dcxtf4zcvua7ivbvlhdwp2p2fnlknieny3y7invap2wqbu"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn blob_ref() {
+        let json = r#"{
+            "$type": "app.bsky.feed.post",
+            "text": "hello",
+            "createdAt": "2024-11-19T13:43:03.905Z",
+            "embed": {
+                "$type": "app.bsky.embed.images",
+                "images": [{
+                    "alt": "",
+                    "image": {
+                        "$type": "blob",
+                        "ref": {"$link": "bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm"},
+                        "mimeType": "image/png",
+                        "size": 12345
+                    }
+                }]
+            }
+        }"#;
+        assert_eq!(
+            cid_of(json).unwrap(),
+            "bafyreicwjnrtbjyenui6opnvtcwd3hdmzuujppvbpbjholig6nfe24aiya"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn legacy_blob() {
+        let json = r#"{
+            "$type": "app.bsky.actor.profile",
+            "displayName": "old",
+            "avatar": {
+                "cid": "bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm",
+                "mimeType": "image/png"
+            }
+        }"#;
+        assert_eq!(
+            cid_of(json).unwrap(),
+            "bafyreih3ggs5ivsnktashbruamtachzupqv7yzrcjctwgtojozapn4tldm"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn invalid_values() {
+        let invalid = [
+            r#"{"link": {"$link": "not a cid"}}"#,
+            r#"{"link": {"$link": "bafyreidwmohm4mqkxcqc2cixys5kvwwirt2qa6456r7iznoaao473lu4m4", "x": 1}}"#,
+            r#"{"bytes": {"$bytes": "!!!"}}"#,
+            r#"{"image": {"$type": "blob", "ref": {"$link": "bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm"}, "size": 1}}"#,
+            r#"{"image": {"$type": "blob", "ref": {"$link": "bafyreidwmohm4mqkxcqc2cixys5kvwwirt2qa6456r7iznoaao473lu4m4"}, "mimeType": "image/png", "size": 1}}"#,
+            r#"{"avatar": {"cid": "nope", "mimeType": "image/png"}}"#,
+        ];
+        for json in invalid.iter() {
+            assert!(cid_of(json).is_err(), "{} should be rejected", json);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn strict_mode() {
+        let json = r#"{"facets": [{"index": {"byteStart": 0, "byteEnd": 3.5}}]}"#;
+        assert!(cid_of(json).is_ok());
+        assert!(strict_error(json).contains("facets[0].index.byteEnd: floats are not allowed"));
+        assert!(strict_error(r#"{"count": 9007199254740992}"#)
+            .contains("count: integer is outside the safe range"));
+
+        let record = JSON::parse(r#"{"langs": ["en"]}"#).unwrap();
+        let langs = Reflect::get(&record, &"langs".into()).unwrap();
+        Reflect::set(&langs, &1.into(), &JsValue::UNDEFINED).unwrap();
+        assert!(strict_error_of(record).contains("langs[1]: undefined is not allowed"));
+    }
+}
@@ -0,0 +1,658 @@
+use crate::ipld_transcode::{format_path, Segment};
+use crate::record_to_cbor;
+use crate::syntax::{is_did, is_handle, is_nsid, is_record_key, is_tid, AtIdentifier, AtUri};
+use crate::value::{self, Value};
+use cid::Cid;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
+use wasm_bindgen::prelude::*;
+
+#[derive(Deserialize)]
+struct LexiconDoc {
+    lexicon: u32,
+    id: String,
+    defs: HashMap<String, Def>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ObjectDef {
+    required: Vec<String>,
+    nullable: Vec<String>,
+    properties: BTreeMap<String, Def>,
+}
+
+// `knownValues` is deliberately not read: the spec makes it a hint, and
+// records may carry values that were added to the list after this schema.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct StringDef {
+    format: Option<String>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    min_graphemes: Option<usize>,
+    max_graphemes: Option<usize>,
+    #[serde(rename = "enum")]
+    one_of: Option<Vec<String>>,
+    #[serde(rename = "const")]
+    constant: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Def {
+    Record {
+        record: ObjectDef,
+    },
+    Object(ObjectDef),
+    #[serde(rename_all = "camelCase")]
+    Array {
+        items: Box<Def>,
+        min_length: Option<usize>,
+        max_length: Option<usize>,
+    },
+    String(StringDef),
+    Integer {
+        minimum: Option<i64>,
+        maximum: Option<i64>,
+        #[serde(rename = "enum")]
+        one_of: Option<Vec<i64>>,
+        #[serde(rename = "const")]
+        constant: Option<i64>,
+    },
+    Boolean {
+        #[serde(rename = "const")]
+        constant: Option<bool>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Bytes {
+        min_length: Option<usize>,
+        max_length: Option<usize>,
+    },
+    CidLink,
+    #[serde(rename_all = "camelCase")]
+    Blob {
+        accept: Option<Vec<String>>,
+        max_size: Option<u64>,
+    },
+    Null,
+    Ref {
+        #[serde(rename = "ref")]
+        target: String,
+    },
+    Union {
+        refs: Vec<String>,
+        #[serde(default)]
+        closed: bool,
+    },
+    Unknown,
+    // tokens, queries, procedures and subscriptions never describe record data
+    #[serde(other)]
+    Other,
+}
+
+/// Splits `reference` into a lexicon id and a def name, relative to the
+/// lexicon `base` it appears in.
+fn split_ref<'r>(base: &'r str, reference: &'r str) -> (&'r str, &'r str) {
+    match reference.split_once('#') {
+        Some(("", name)) => (base, name),
+        Some((id, name)) => (id, name),
+        None => (reference, "main"),
+    }
+}
+
+fn is_datetime(s: &str) -> bool {
+    let b = s.as_bytes();
+    let digits = |range: std::ops::Range<usize>| b[range].iter().all(u8::is_ascii_digit);
+    if b.len() < 20
+        || !digits(0..4)
+        || b[4] != b'-'
+        || !digits(5..7)
+        || b[7] != b'-'
+        || !digits(8..10)
+        || b[10] != b'T'
+        || !digits(11..13)
+        || b[13] != b':'
+        || !digits(14..16)
+        || b[16] != b':'
+        || !digits(17..19)
+    {
+        return false;
+    }
+    let mut rest = &s[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return false;
+        }
+        rest = &fraction[len..];
+    }
+    match rest.as_bytes() {
+        b"Z" => true,
+        [b'+' | b'-', h1, h2, b':', m1, m2] => {
+            [h1, h2, m1, m2].iter().all(|c| c.is_ascii_digit()) && rest != "-00:00"
+        }
+        _ => false,
+    }
+}
+
+fn is_language(s: &str) -> bool {
+    let mut subtags = s.split('-');
+    let primary = subtags.next().unwrap_or("");
+    let primary_ok = ((2..=3).contains(&primary.len())
+        && primary.bytes().all(|c| c.is_ascii_alphabetic()))
+        || primary == "i"
+        || primary == "x";
+    primary_ok
+        && subtags.all(|tag| {
+            (1..=8).contains(&tag.len()) && tag.bytes().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn is_uri(s: &str) -> bool {
+    match s.split_once(':') {
+        Some((scheme, rest)) => {
+            s.len() <= 8192
+                && !rest.is_empty()
+                && scheme
+                    .as_bytes()
+                    .first()
+                    .is_some_and(u8::is_ascii_alphabetic)
+                && scheme
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || b"+.-".contains(&c))
+                && !s.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn check_format(format: &str, s: &str) -> bool {
+    match format {
+        "datetime" => is_datetime(s),
+        "at-uri" => AtUri::from_str(s).is_ok(),
+        "did" => is_did(s),
+        "handle" => is_handle(s),
+        "at-identifier" => AtIdentifier::from_str(s).is_ok(),
+        "nsid" => is_nsid(s),
+        "cid" => Cid::from_str(s).is_ok(),
+        "language" => is_language(s),
+        "record-key" => is_record_key(s),
+        "tid" => is_tid(s),
+        "uri" => is_uri(s),
+        // unknown formats are accepted, so newer schemas still load
+        _ => true,
+    }
+}
+
+fn mime_type_accepted(accept: &[String], mime_type: &str) -> bool {
+    accept
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some("*") => true,
+            Some(prefix) => mime_type
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('/')),
+            None => pattern == mime_type,
+        })
+}
+
+struct Validator<'a> {
+    lexicons: &'a Lexicons,
+    path: Vec<Segment>,
+    errors: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, msg: &str) {
+        self.errors
+            .push(format!("{}: {}", format_path(&self.path), msg));
+    }
+
+    fn resolve(&self, base: &str, reference: &str) -> Option<(&'a str, &'a Def)> {
+        let (id, name) = split_ref(base, reference);
+        let doc = self.lexicons.docs.get(id)?;
+        doc.defs.get(name).map(|def| (doc.id.as_str(), def))
+    }
+
+    fn object(&mut self, lexicon: &str, def: &ObjectDef, map: &BTreeMap<String, Value>) {
+        for key in def.required.iter() {
+            if !map.contains_key(key) {
+                self.path.push(Segment::Key(key.clone()));
+                self.error("required field is missing");
+                self.path.pop();
+            }
+        }
+        for (key, property) in def.properties.iter() {
+            let value = match map.get(key) {
+                Some(value) => value,
+                None => continue,
+            };
+            self.path.push(Segment::Key(key.clone()));
+            if *value == Value::Null && !def.nullable.contains(key) {
+                self.error("field may not be null");
+            } else if *value != Value::Null {
+                self.value(lexicon, property, value);
+            }
+            self.path.pop();
+        }
+    }
+
+    fn string(&mut self, def: &StringDef, s: &str) {
+        if let Some(ref constant) = def.constant {
+            if s != constant {
+                self.error(&format!("must be {:?}", constant));
+            }
+        }
+        if let Some(ref one_of) = def.one_of {
+            if !one_of.iter().any(|option| option == s) {
+                self.error("is not one of the allowed values");
+            }
+        }
+        if def.min_length.is_some_and(|min| s.len() < min) {
+            self.error("string is too short");
+        }
+        if def.max_length.is_some_and(|max| s.len() > max) {
+            self.error("string is too long");
+        }
+        if def.min_graphemes.is_some() || def.max_graphemes.is_some() {
+            let graphemes = s.graphemes(true).count();
+            if def.min_graphemes.is_some_and(|min| graphemes < min) {
+                self.error("string has too few graphemes");
+            }
+            if def.max_graphemes.is_some_and(|max| graphemes > max) {
+                self.error("string has too many graphemes");
+            }
+        }
+        if let Some(ref format) = def.format {
+            if !check_format(format, s) {
+                self.error(&format!("string is not a valid {}", format));
+            }
+        }
+    }
+
+    fn union(&mut self, lexicon: &str, refs: &[String], closed: bool, value: &Value) {
+        let map = match value {
+            Value::Map(map) => map,
+            _ => return self.error("union value must be an object"),
+        };
+        let value_type = match map.get("$type") {
+            Some(Value::String(value_type)) => value_type,
+            _ => return self.error("union value must have a $type"),
+        };
+
+        let wanted = split_ref(lexicon, value_type);
+        for reference in refs {
+            if split_ref(lexicon, reference) == wanted {
+                return match self.resolve(lexicon, reference) {
+                    Some((lexicon, def)) => self.value(lexicon, def, value),
+                    None => self.error(&format!("unknown lexicon {}", reference)),
+                };
+            }
+        }
+        if closed {
+            self.error(&format!("{} is not allowed here", value_type));
+        }
+    }
+
+    fn value(&mut self, lexicon: &str, def: &Def, value: &Value) {
+        match (def, value) {
+            (Def::Record { record }, Value::Map(map)) | (Def::Object(record), Value::Map(map)) => {
+                self.object(lexicon, record, map)
+            }
+            (
+                Def::Array {
+                    items,
+                    min_length,
+                    max_length,
+                },
+                Value::Array(values),
+            ) => {
+                if min_length.is_some_and(|min| values.len() < min) {
+                    self.error("array is too short");
+                }
+                if max_length.is_some_and(|max| values.len() > max) {
+                    self.error("array is too long");
+                }
+                for (i, item) in values.iter().enumerate() {
+                    self.path.push(Segment::Index(i));
+                    self.value(lexicon, items, item);
+                    self.path.pop();
+                }
+            }
+            (Def::String(def), Value::String(s)) => self.string(def, s),
+            (
+                Def::Integer {
+                    minimum,
+                    maximum,
+                    one_of,
+                    constant,
+                },
+                Value::Integer(n),
+            ) => {
+                if minimum.is_some_and(|min| *n < min) || maximum.is_some_and(|max| *n > max) {
+                    self.error("integer is out of range");
+                }
+                if one_of.as_ref().is_some_and(|one_of| !one_of.contains(n)) {
+                    self.error("is not one of the allowed values");
+                }
+                if constant.is_some_and(|constant| *n != constant) {
+                    self.error(&format!("must be {}", constant.unwrap()));
+                }
+            }
+            (Def::Boolean { constant }, Value::Bool(b)) => {
+                if constant.is_some_and(|constant| *b != constant) {
+                    self.error(&format!("must be {}", constant.unwrap()));
+                }
+            }
+            (
+                Def::Bytes {
+                    min_length,
+                    max_length,
+                },
+                Value::Bytes(bytes),
+            ) => {
+                if min_length.is_some_and(|min| bytes.len() < min) {
+                    self.error("bytes are too short");
+                }
+                if max_length.is_some_and(|max| bytes.len() > max) {
+                    self.error("bytes are too long");
+                }
+            }
+            (Def::CidLink, Value::Link(_)) => {}
+            (Def::Blob { accept, max_size }, Value::Map(map)) => {
+                if let Some(Value::Integer(size)) = map.get("size") {
+                    if max_size.is_some_and(|max| *size as u64 > max) {
+                        self.error("blob is too large");
+                    }
+                }
+                match map.get("mimeType") {
+                    Some(Value::String(mime_type)) => {
+                        if accept
+                            .as_ref()
+                            .is_some_and(|accept| !mime_type_accepted(accept, mime_type))
+                        {
+                            self.error(&format!("blob type {} is not accepted", mime_type));
+                        }
+                    }
+                    _ => self.error("expected a blob"),
+                }
+            }
+            (Def::Null, Value::Null) => {}
+            (Def::Ref { target }, _) => match self.resolve(lexicon, target) {
+                Some((lexicon, def)) => self.value(lexicon, def, value),
+                None => self.error(&format!("unknown lexicon {}", target)),
+            },
+            (Def::Union { refs, closed }, _) => self.union(lexicon, refs, *closed, value),
+            (Def::Unknown, Value::Map(_)) => {}
+            (Def::Other, _) => {}
+            (def, _) => self.error(&format!("expected {}", def_name(def))),
+        }
+    }
+}
+
+fn def_name(def: &Def) -> &'static str {
+    match def {
+        Def::Record { .. } | Def::Object(_) | Def::Unknown => "an object",
+        Def::Array { .. } => "an array",
+        Def::String(_) => "a string",
+        Def::Integer { .. } => "an integer",
+        Def::Boolean { .. } => "a boolean",
+        Def::Bytes { .. } => "bytes",
+        Def::CidLink => "a cid link",
+        Def::Blob { .. } => "a blob",
+        Def::Null => "null",
+        Def::Ref { .. } | Def::Union { .. } | Def::Other => "a value",
+    }
+}
+
+/// A set of lexicon documents that records can be validated against.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Lexicons {
+    docs: HashMap<String, LexiconDoc>,
+}
+
+impl Lexicons {
+    /// Validates `record` against the record schema named by its `$type`,
+    /// returning every violation found.
+    pub fn check(&self, record: &Value) -> Result<(), Vec<String>> {
+        let record_type = match record {
+            Value::Map(map) => match map.get("$type") {
+                Some(Value::String(record_type)) => record_type.as_str(),
+                _ => return Err(vec!["(root): record must have a $type".into()]),
+            },
+            _ => return Err(vec!["(root): record must be an object".into()]),
+        };
+
+        let mut validator = Validator {
+            lexicons: self,
+            path: Vec::new(),
+            errors: Vec::new(),
+        };
+        match validator.resolve(record_type, record_type) {
+            Some((lexicon, def @ Def::Record { .. })) => validator.value(lexicon, def, record),
+            Some(_) => validator.error(&format!("{} is not a record type", record_type)),
+            None => validator.error(&format!("unknown lexicon {}", record_type)),
+        }
+
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(validator.errors)
+        }
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_LEXICONS: &str = r#"
+export interface LexiconDocument {
+  lexicon: 1;
+  id: string;
+  defs: Record<string, unknown>;
+  [key: string]: unknown;
+}
+
+export interface Lexicons {
+  add(doc: LexiconDocument): void;
+  validate(record: AtprotoRecord): void;
+}
+"#;
+
+#[wasm_bindgen]
+impl Lexicons {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Lexicons {
+        Lexicons::default()
+    }
+
+    /// Adds a lexicon document (the parsed JSON), replacing any earlier
+    /// document with the same id.
+    #[wasm_bindgen(skip_typescript)]
+    pub fn add(&mut self, doc: JsValue) -> Result<(), JsValue> {
+        let doc: LexiconDoc = serde_wasm_bindgen::from_value(doc)
+            .map_err(|err| format!("invalid lexicon: {}", err))?;
+        if doc.lexicon != 1 {
+            return Err("unsupported lexicon version".into());
+        }
+        if !is_nsid(&doc.id) {
+            return Err("lexicon id must be an nsid".into());
+        }
+        self.docs.insert(doc.id.clone(), doc);
+        Ok(())
+    }
+
+    /// Validates `record` (the same object passed to
+    /// `authenticate_post_with_doc`) against the schema for its `$type`.
+    #[wasm_bindgen(skip_typescript)]
+    pub fn validate(&self, record: JsValue) -> Result<(), JsValue> {
+        let record = value::decode_cbor(&record_to_cbor(record, false)?)?;
+        self.check(&record).map_err(|errors| {
+            format!("record doesn't match its lexicon: {}", errors.join("; ")).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys::js_sys::JSON;
+
+    const POST: &str = r##"{
+        "lexicon": 1,
+        "id": "app.bsky.feed.post",
+        "defs": {
+            "main": {
+                "type": "record",
+                "key": "tid",
+                "record": {
+                    "type": "object",
+                    "required": ["text", "createdAt"],
+                    "properties": {
+                        "text": {"type": "string", "maxLength": 3000, "maxGraphemes": 300},
+                        "createdAt": {"type": "string", "format": "datetime"},
+                        "langs": {"type": "array", "maxLength": 3, "items": {"type": "string", "format": "language"}},
+                        "reply": {"type": "ref", "ref": "#replyRef"},
+                        "embed": {"type": "union", "refs": ["app.bsky.embed.images"]},
+                        "tags": {"type": "array", "items": {"type": "string", "knownValues": ["a"]}}
+                    }
+                }
+            },
+            "replyRef": {
+                "type": "object",
+                "required": ["root", "parent"],
+                "properties": {
+                    "root": {"type": "ref", "ref": "com.atproto.repo.strongRef"},
+                    "parent": {"type": "ref", "ref": "com.atproto.repo.strongRef"}
+                }
+            }
+        }
+    }"##;
+
+    const STRONG_REF: &str = r#"{
+        "lexicon": 1,
+        "id": "com.atproto.repo.strongRef",
+        "defs": {
+            "main": {
+                "type": "object",
+                "required": ["uri", "cid"],
+                "properties": {
+                    "uri": {"type": "string", "format": "at-uri"},
+                    "cid": {"type": "string", "format": "cid"}
+                }
+            }
+        }
+    }"#;
+
+    const IMAGES: &str = r##"{
+        "lexicon": 1,
+        "id": "app.bsky.embed.images",
+        "defs": {
+            "main": {
+                "type": "object",
+                "required": ["images"],
+                "properties": {
+                    "images": {"type": "array", "maxLength": 4, "items": {"type": "ref", "ref": "#image"}}
+                }
+            },
+            "image": {
+                "type": "object",
+                "required": ["image", "alt"],
+                "properties": {
+                    "image": {"type": "blob", "accept": ["image/*"], "maxSize": 1000000},
+                    "alt": {"type": "string"}
+                }
+            }
+        }
+    }"##;
+
+    fn lexicons() -> Lexicons {
+        let mut lexicons = Lexicons::new();
+        for doc in [POST, STRONG_REF, IMAGES] {
+            lexicons.add(JSON::parse(doc).unwrap()).unwrap();
+        }
+        lexicons
+    }
+
+    fn check(json: &str) -> Result<(), Vec<String>> {
+        let record =
+            value::decode_cbor(&record_to_cbor(JSON::parse(json).unwrap(), false).unwrap())
+                .unwrap();
+        lexicons().check(&record)
+    }
+
+    #[wasm_bindgen_test]
+    fn valid_post() {
+        check(
+            r#"{
+                "$type": "app.bsky.feed.post",
+                "text": "hello 👋🏽",
+                "createdAt": "2024-08-01T12:00:00.000Z",
+                "langs": ["en", "pt-BR"],
+                "tags": ["not-a-known-value"],
+                "reply": {
+                    "root": {"uri": "at://did:plc:ewvi7nxzyoun6zhxrhs64oiz/app.bsky.feed.post/3kvr3ymffwc2m", "cid": "bafyreieiof45ascpryjyodac4j5chhi5o4svygejbm4tp2hfouitxpi7ba"},
+                    "parent": {"uri": "at://did:plc:ewvi7nxzyoun6zhxrhs64oiz/app.bsky.feed.post/3kvr3ymffwc2m", "cid": "bafyreieiof45ascpryjyodac4j5chhi5o4svygejbm4tp2hfouitxpi7ba"}
+                },
+                "embed": {
+                    "$type": "app.bsky.embed.images",
+                    "images": [{"alt": "", "image": {"$type": "blob", "ref": {"$link": "bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm"}, "mimeType": "image/png", "size": 12345}}]
+                }
+            }"#,
+        )
+        .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    fn violations() {
+        let errors = check(
+            r#"{
+                "$type": "app.bsky.feed.post",
+                "text": 5,
+                "createdAt": "yesterday",
+                "langs": ["en", "not a language"],
+                "reply": {"root": {"uri": "https://example.com", "cid": "nope"}},
+                "embed": {
+                    "$type": "app.bsky.embed.images",
+                    "images": [{"alt": "", "image": {"$type": "blob", "ref": {"$link": "bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm"}, "mimeType": "video/mp4", "size": 12345}}]
+                }
+            }"#,
+        )
+        .unwrap_err();
+        let expected = [
+            "text: expected a string",
+            "createdAt: string is not a valid datetime",
+            "langs[1]: string is not a valid language",
+            "reply.parent: required field is missing",
+            "reply.root.uri: string is not a valid at-uri",
+            "reply.root.cid: string is not a valid cid",
+            "embed.images[0].image: blob type video/mp4 is not accepted",
+        ];
+        for error in expected {
+            assert!(
+                errors.iter().any(|e| e == error),
+                "missing {:?} in {:?}",
+                error,
+                errors
+            );
+        }
+        assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+    }
+
+    #[wasm_bindgen_test]
+    fn graphemes_and_unions() {
+        let text = "👋🏽".repeat(400);
+        let errors = check(&format!(
+            r#"{{"$type": "app.bsky.feed.post", "text": "{}", "createdAt": "2024-08-01T12:00:00Z", "embed": {{"$type": "app.bsky.embed.video"}}}}"#,
+            text
+        ))
+        .unwrap_err();
+        assert!(errors.contains(&"text: string is too long".to_string()));
+        assert!(errors.contains(&"text: string has too many graphemes".to_string()));
+        // open unions let unknown types through
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+    }
+}
@@ -1,18 +1,119 @@
+mod account;
+mod blob;
+mod blockstore;
+mod car;
+mod hasher;
+mod indexed_car;
 mod ipld_transcode;
+mod lexicon;
+mod mst;
+mod records;
+mod repo;
+mod rev_store;
+mod syntax;
+#[cfg(test)]
+mod testing;
+mod thread;
+mod tid;
+mod transport;
+mod value;
+mod xrpc;
 use cid::Cid;
-use futures_util::stream::StreamExt;
 use k256::ecdsa::signature::Verifier as k256Verifier;
 use serde::{Deserialize, Serialize};
-use sha2::Digest;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
-use std::ops::Add;
 use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::js_sys::Uint8Array;
-use web_sys::{Request, RequestInit, RequestMode, Response};
+use ipld_transcode::{format_path, Segment};
+use syntax::{AtUri, Did, Nsid, RecordKey, Tid};
+use transport::{fetch, FetchOptions};
+use value::Value;
+use web_sys::js_sys::{Array, Object, Reflect, Uint8Array};
+
+// Exports that take or return `JsValue`s skip the generated `any` typings and
+// are declared by hand in a custom section of their module instead.
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &str = r#"
+/** A record in its atproto JSON form, with `$link` and `$bytes` objects. */
+export interface AtprotoRecord {
+  $type: string;
+  [key: string]: unknown;
+}
+
+export interface DidDocument {
+  id: string;
+  alsoKnownAs?: string[];
+  verificationMethod?: {
+    id: string;
+    type?: string;
+    controller?: string;
+    publicKeyMultibase: string;
+  }[];
+  service?: {
+    id: string;
+    type: string;
+    serviceEndpoint: string;
+  }[];
+}
+
+export interface VerifyOptions {
+  maxCarBytes?: number;
+  maxCarBlocks?: number;
+  strictDataModel?: boolean;
+  returnRecord?: boolean;
+  maxClockSkewMs?: number;
+  lastSeenRev?: string;
+  strictCbor?: boolean;
+  checkAccountStatus?: boolean;
+  timeoutMs?: number;
+  retries?: number;
+  maxBlobBytes?: number;
+  signal?: AbortSignal;
+}
+
+export interface LatestCommit {
+  cid: string;
+  rev: string;
+}
+
+export interface VerificationReport {
+  did: string;
+  /** Absent for `verify_record_in_archive`. */
+  pds?: string;
+  signingKey: string;
+  curve: "k256" | "p256" | "unknown";
+  commitCid: string;
+  rev: string;
+  data: string;
+  /** How many MST nodes lie above the one holding the record. */
+  recordDepth: number;
+  blocksChecked: number;
+  didDocSource: string;
+  /** Only with `checkAccountStatus`. */
+  active?: boolean;
+  status?: string;
+  latestCommit?: LatestCommit;
+  isLatestCommit?: boolean;
+  /** Only with `returnRecord`. */
+  record?: AtprotoRecord;
+}
+
+export interface VerifiedRecord {
+  cid: string;
+  record: AtprotoRecord;
+  commitRev: string;
+  commitCid: string;
+}
+
+export function verify_record_with_doc(uri: string, cid: string, record: AtprotoRecord, did_doc: DidDocument, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_record(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function authenticate_post_with_doc(uri: string, cid: string, record: AtprotoRecord, did_doc: DidDocument, options?: VerifyOptions): Promise<VerificationReport>;
+export function authenticate_post(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function fetch_verified_record(uri: string, options?: VerifyOptions): Promise<VerifiedRecord>;
+"#;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -66,6 +167,95 @@ impl DidDocument {
     }
 }
 
+const DEFAULT_CLOCK_SKEW_MS: u64 = 5 * 60 * 1000;
+const DEFAULT_MAX_BLOB_BYTES: u64 = 100 * 1024 * 1024;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VerifyOptions {
+    pub max_car_bytes: Option<u64>,
+    pub max_car_blocks: Option<usize>,
+    pub strict_data_model: bool,
+    pub return_record: bool,
+    pub max_clock_skew_ms: Option<u64>,
+    pub last_seen_rev: Option<String>,
+    pub strict_cbor: bool,
+    pub check_account_status: bool,
+    pub timeout_ms: Option<u32>,
+    pub retries: Option<u32>,
+    pub max_blob_bytes: Option<u64>,
+    #[serde(with = "serde_wasm_bindgen::preserve")]
+    pub signal: JsValue,
+}
+
+impl VerifyOptions {
+    pub fn from_js(options: JsValue) -> Result<VerifyOptions, JsValue> {
+        if options.is_undefined() || options.is_null() {
+            return Ok(VerifyOptions::default());
+        }
+        Ok(serde_wasm_bindgen::from_value(options)?)
+    }
+
+    /// Rejects commits dated too far in the future, and commits older than
+    /// the last rev the caller saw, which could be replays hiding a
+    /// deletion.
+    fn check_rev(&self, rev: &str) -> Result<(), JsValue> {
+        let rev = Tid::from_str(rev).map_err(|_| "commit rev is not a valid tid")?;
+
+        let skew = self.max_clock_skew_ms.unwrap_or(DEFAULT_CLOCK_SKEW_MS);
+        let now_micros = web_sys::js_sys::Date::now() as u64 * 1000;
+        if rev.timestamp_micros() > now_micros.saturating_add(skew * 1000) {
+            return Err("commit rev is in the future".into());
+        }
+
+        if let Some(ref last_seen) = self.last_seen_rev {
+            let last_seen =
+                Tid::from_str(last_seen).map_err(|_| "lastSeenRev is not a valid tid")?;
+            if rev < last_seen {
+                return Err("commit rev is older than the last seen rev".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// In strict mode, rejects blocks that aren't canonical DAG-CBOR. MST
+    /// nodes are always held to this by `mst::decode_node`.
+    fn check_cbor(&self, what: &str, block: &[u8]) -> Result<(), JsValue> {
+        if self.strict_cbor && value::check_canonical(block).is_err() {
+            return Err(format!("{} is not canonical dag-cbor", what).into());
+        }
+        Ok(())
+    }
+
+    fn fetch_options(&self) -> Result<FetchOptions, JsValue> {
+        let defaults = FetchOptions::default();
+        let signal = if self.signal.is_undefined() || self.signal.is_null() {
+            None
+        } else {
+            let signal = self.signal.clone().dyn_into::<web_sys::AbortSignal>();
+            Some(signal.map_err(|_| "signal must be an AbortSignal")?)
+        };
+        Ok(FetchOptions {
+            timeout_ms: self.timeout_ms.unwrap_or(defaults.timeout_ms),
+            retries: self.retries.unwrap_or(defaults.retries),
+            signal,
+        })
+    }
+
+    /// The most a blob ref without a declared `size` may download.
+    fn max_blob_bytes(&self) -> u64 {
+        self.max_blob_bytes.unwrap_or(DEFAULT_MAX_BLOB_BYTES)
+    }
+
+    fn car_limits(&self) -> car::CarLimits {
+        let defaults = car::CarLimits::default();
+        car::CarLimits {
+            max_bytes: self.max_car_bytes.unwrap_or(defaults.max_bytes),
+            max_blocks: self.max_car_blocks.unwrap_or(defaults.max_blocks),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct SignedCommitObject<'a> {
     did: String,
@@ -85,351 +275,512 @@ pub struct UnsignedCommitObject {
     version: u16,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct IPLDEntry {
-    p: u32,
-    k: String,
-    v: Cid,
-    t: Option<Cid>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct IPLDNode {
-    l: Option<Cid>,
-    e: Vec<IPLDEntry>,
-}
-
-struct DFSState {
-    found: bool,
-    min: Option<String>,
-    max: Option<String>,
-    depth: Option<u32>,
-}
-
-fn dfs(
-    tree: &HashMap<Vec<u8>, Vec<u8>>,
-    visited: &mut HashSet<Cid>,
-    start: Option<Cid>,
-    target: Cid,
-) -> Result<DFSState, JsValue> {
-    let start = match start {
-        Some(cid) => cid,
-        None => {
-            return Ok(DFSState {
-                found: false,
-                min: None,
-                max: None,
-                depth: None,
-            });
-        }
-    };
-    if visited.contains(&start) {
-        panic!("this tree is not a tree?");
+/// serde_wasm_bindgen reads `undefined` just like `null`, so strict mode has
+/// to look for it on the JS side.
+fn find_undefined(value: &JsValue, path: &mut Vec<Segment>) -> Option<String> {
+    if value.is_undefined() {
+        return Some(format_path(path));
     }
-    visited.insert(start);
-    let block = match tree.get(&start.to_bytes()) {
-        Some(block) => block,
-        None => {
-            return Ok(DFSState {
-                found: false,
-                min: None,
-                max: None,
-                depth: None,
-            });
-        }
-    };
-    let node: IPLDNode = serde_ipld_dagcbor::from_slice(block).unwrap();
-    let left_state = dfs(tree, visited, node.l, target)?;
-
-    let mut key = String::new();
-    let mut found = left_state.found;
-    let mut depth: Option<u32> = None;
-    let mut first_key: Option<String> = None;
-    let mut last_key: Option<String> = None;
-
-    for entry in node.e {
-        if entry.v == target {
-            found = true;
-        }
-        key.truncate(entry.p as usize);
-        key = key.add(&entry.k);
-
-        let key_digest = sha2::Sha256::digest(&key);
-        let mut zero_count = 0u32;
-        'count: for byte in key_digest {
-            for bit in (0..8u32).rev() {
-                if (byte >> bit & 1) != 0 {
-                    break 'count;
-                }
-                zero_count += 1;
-            }
-        }
-
-        let this_depth = zero_count / 2;
-        match depth {
-            None => {
-                depth = Some(this_depth);
-            }
-            Some(depth) => {
-                if depth != this_depth {
-                    return Err("node has entries with different depths".into());
-                }
-            }
-        }
-
-        if last_key.is_none() {
-            first_key = Some(key.clone());
-            last_key = Some(key.clone());
-        }
-
-        let last_key_ = last_key.unwrap();
-        if last_key_ > key {
-            return Err("entries are out of order".into());
-        }
-
-        let right_state = dfs(tree, visited, entry.t, target)?;
-
-        if let Some(min) = right_state.min {
-            if min < last_key_ {
-                return Err("entries are out of order".into());
-            }
-        }
-        found = found || right_state.found;
-
-        if let Some(left_depth) = left_state.depth {
-            if left_depth >= this_depth {
-                return Err("depths are out of order".into());
+    if Array::is_array(value) {
+        let array: &Array = value.unchecked_ref();
+        for (index, item) in array.iter().enumerate() {
+            path.push(Segment::Index(index));
+            if let Some(found) = find_undefined(&item, path) {
+                return Some(found);
             }
+            path.pop();
         }
-        if let Some(right_depth) = right_state.depth {
-            if right_depth >= this_depth {
-                return Err("depths are out of order".into());
+    } else if value.is_object() && !value.is_instance_of::<Uint8Array>() {
+        for entry in Object::entries(value.unchecked_ref()).iter() {
+            let entry: Array = entry.unchecked_into();
+            path.push(Segment::Key(entry.get(0).as_string().unwrap_or_default()));
+            if let Some(found) = find_undefined(&entry.get(1), path) {
+                return Some(found);
             }
+            path.pop();
         }
-
-        last_key = match right_state.max {
-            Some(key) => Some(key),
-            None => Some(key.clone()),
-        };
     }
-
-    if let Some(left_max) = left_state.max {
-        if let Some(ref first_key) = first_key {
-            if &left_max > first_key {
-                return Err("entries are out of order".into());
-            }
-        }
-    }
-
-    Ok(DFSState {
-        found,
-        min: first_key,
-        max: last_key,
-        depth,
-    })
+    None
 }
 
-#[wasm_bindgen]
-pub async fn authenticate_post_with_doc(
-    uri: &str,
-    cid: &str,
-    record: JsValue,
-    did_doc: JsValue,
-) -> Result<(), JsValue> {
-    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
-
-    let cid = Cid::from_str(cid).expect("couldn't parse given cid");
-    let (hash_type, hash_digest, hash_len) = cid.hash().into_inner();
-
-    if hash_type != 0x12 || hash_len != 0x20 {
-        return Err("unexpected cid type".into());
+fn record_to_cbor(record: JsValue, strict: bool) -> Result<Vec<u8>, JsValue> {
+    if strict {
+        if let Some(path) = find_undefined(&record, &mut Vec::new()) {
+            return Err(format!("{}: undefined is not allowed", path).into());
+        }
     }
 
     let deserializer = serde_wasm_bindgen::Deserializer::from(record);
     let writer = serde_ipld_dagcbor::ser::BufWriter::new(Vec::new());
     let mut serializer = serde_ipld_dagcbor::ser::Serializer::new(writer);
-    ipld_transcode::transcode(deserializer, &mut serializer).unwrap();
+    let result = if strict {
+        ipld_transcode::transcode_strict(deserializer, &mut serializer)
+    } else {
+        ipld_transcode::transcode(deserializer, &mut serializer)
+    };
+    result.map_err(|err| format!("couldn't encode record: {}", err))?;
 
     let cbor_writer = serializer.into_inner();
-    let cbor = cbor_writer.buffer();
+    let cbor = cbor_writer.buffer().to_vec();
 
-    let record_hash = sha2::Sha256::digest(cbor);
+    // values outside the data model (floats) are only rejected in strict mode
+    if let Ok(value) = value::decode_cbor(&cbor) {
+        value::validate_blobs(&value)?;
+    }
 
-    if &hash_digest[..32] != record_hash.as_slice() {
-        return Err(format!(
-            "given cid doesn't match given record, {:?} != {:?}, {:?}",
-            &hash_digest[..32],
-            &record_hash.as_slice(),
-            cbor
+    Ok(cbor)
+}
+
+fn cid_for_cbor(cbor: &[u8]) -> Cid {
+    hasher::cid_for(hasher::DAG_CBOR, cbor)
+}
+
+/// A record's `$type` names its own collection, and has to agree with the
+/// collection it is stored under.
+fn check_record_type(collection: &Nsid, record_type: Option<&str>) -> Result<(), JsValue> {
+    match record_type {
+        Some(record_type) if record_type == collection.as_str() => Ok(()),
+        Some(record_type) => Err(format!(
+            "record $type {} doesn't match collection {}",
+            record_type, collection
         )
-        .into());
+        .into()),
+        None => Err("record has no $type".into()),
     }
+}
 
-    if &uri[..5] != "at://" {
-        return Err("invalid record uri".into());
+/// Fails unless `cid` could be the cid of a record.
+fn check_record_cid(cid: &Cid) -> Result<(), JsValue> {
+    if cid.codec() == hasher::RAW {
+        return Err("given cid is for a blob, not a record".into());
     }
+    hasher::check_codec(cid, hasher::DAG_CBOR)
+}
 
-    let parts: Vec<&str> = uri[5..].split('/').collect();
-    if parts.len() != 3 {
-        return Err("invalid record uri".into());
+/// The `$type` of a decoded record, if it has one.
+fn value_record_type(record: &Value) -> Option<&str> {
+    match record {
+        Value::Map(map) => match map.get("$type") {
+            Some(Value::String(record_type)) => Some(record_type.as_str()),
+            _ => None,
+        },
+        _ => None,
     }
+}
 
-    if parts[0] != did_doc.id {
-        return Err("record uri did doesn't match did doc id".into());
+fn split_record_uri(uri: &str) -> Result<(Did, Nsid, RecordKey), JsValue> {
+    let uri = AtUri::from_str(uri)?;
+    let (did, collection, rkey) = uri.record()?;
+    Ok((did.clone(), collection.clone(), rkey.clone()))
+}
+
+fn verify_commit<'a>(
+    block: &'a [u8],
+    did: &str,
+    signing_key: &[u8],
+) -> Result<SignedCommitObject<'a>, JsValue> {
+    let root_object: SignedCommitObject =
+        serde_ipld_dagcbor::from_slice(block).map_err(|_| "couldn't decode commit")?;
+    if root_object.did != did {
+        return Err("did from car doesn't match did from uri".into());
     }
+    let unsigned_object = UnsignedCommitObject {
+        did: root_object.did.clone(),
+        version: root_object.version,
+        data: root_object.data,
+        rev: root_object.rev.clone(),
+        prev: root_object.prev,
+    };
+    let data_signed =
+        serde_ipld_dagcbor::to_vec(&unsigned_object).map_err(|_| "couldn't encode commit")?;
 
-    let opts = RequestInit::new();
-    opts.set_method("GET");
-    opts.set_mode(RequestMode::Cors);
+    if root_object.sig.len() != 64 {
+        return Err("unexpected signature length".into());
+    }
+    let sc_r: [u8; 32] = root_object.sig[..32].try_into().unwrap();
+    let sc_s: [u8; 32] = root_object.sig[32..].try_into().unwrap();
+
+    let result = match signing_key {
+        [0xe7, 0x01, key @ ..] => {
+            let pub_key = k256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+                .map_err(|_| "invalid k256 signing key")?;
+            let signature = k256::ecdsa::Signature::from_scalars(sc_r, sc_s)
+                .map_err(|_| "malformed signature")?;
+            pub_key.verify(&data_signed, &signature)
+        }
+        [0x80, 0x24, key @ ..] => {
+            let pub_key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+                .map_err(|_| "invalid p256 signing key")?;
+            let signature = p256::ecdsa::Signature::from_scalars(sc_r, sc_s)
+                .map_err(|_| "malformed signature")?;
+            pub_key.verify(&data_signed, &signature)
+        }
+        _ => {
+            return Err("unknown signing key format".into());
+        }
+    };
+    if result.is_err() {
+        return Err("signature not verified".into());
+    }
 
-    let window = web_sys::window().unwrap();
+    Ok(root_object)
+}
 
+/// Fetches `com.atproto.sync.getRecord` from `pds`, streaming and checking
+/// every block of the returned CAR.
+async fn fetch_record_car(
+    pds: &str,
+    did: &Did,
+    collection: &Nsid,
+    rkey: &RecordKey,
+    limits: car::CarLimits,
+    fetch_options: &FetchOptions,
+) -> Result<(Vec<Cid>, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
     let url = format!(
         "{}/xrpc/com.atproto.sync.getRecord?did={}&collection={}&rkey={}",
-        did_doc.get_pds()?,
-        parts[0],
-        parts[1],
-        parts[2]
+        pds, did, collection, rkey
     );
-    let request = Request::new_with_str_and_init(&url, &opts)?;
+    let resp = fetch(&url, fetch_options).await?;
+    xrpc::expect_car(&url, &resp)?;
 
-    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    if !resp_value.is_instance_of::<Response>() {
-        return Err("could not get response".into());
-    }
-    let resp: Response = resp_value.dyn_into().unwrap();
+    resp.read(car::read_response(&resp, limits)).await
+}
 
-    let array_buffer = JsFuture::from(resp.array_buffer()?).await?;
-    let array = Uint8Array::new(AsRef::<JsValue>::as_ref(&array_buffer));
-    let bytes = array.to_vec();
+/// What a successful verification established, for display and for logs.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationReport<'a> {
+    did: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pds: Option<&'a str>,
+    signing_key: &'a str,
+    curve: &'static str,
+    commit_cid: String,
+    rev: String,
+    data: String,
+    /// How many MST nodes lie above the one holding the record.
+    record_depth: u32,
+    blocks_checked: usize,
+    did_doc_source: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latest_commit: Option<account::LatestCommit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_latest_commit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<value::Json<'a>>,
+}
 
-    let car_reader = iroh_car::CarReader::new(bytes.as_slice()).await;
-    if car_reader.is_err() {
-        return Err("Failed to decode CAR".into());
+fn key_curve(signing_key: &[u8]) -> &'static str {
+    match signing_key.get(..2) {
+        Some([0xe7, 0x01]) => "k256",
+        Some([0x80, 0x24]) => "p256",
+        _ => "unknown",
     }
+}
 
-    let car_reader = car_reader.unwrap();
-    let header = car_reader.header().clone();
-    let mut stream = Box::pin(car_reader.stream());
+async fn authenticate(
+    uri: &str,
+    cid: &str,
+    record: JsValue,
+    did_doc: DidDocument,
+    options: VerifyOptions,
+    did_doc_source: &str,
+) -> Result<JsValue, JsValue> {
+    let fetch_options = options.fetch_options()?;
+    let (did, collection, rkey) = split_record_uri(uri)?;
+
+    let cid = Cid::from_str(cid).map_err(|_| "couldn't parse given cid")?;
+    check_record_cid(&cid)?;
+
+    let record_type = Reflect::get(&record, &"$type".into())
+        .ok()
+        .and_then(|record_type| record_type.as_string());
+    check_record_type(&collection, record_type.as_deref())?;
+    let cbor = record_to_cbor(record, options.strict_data_model)?;
+
+    if hasher::verify(&cid, &cbor).is_err() {
+        return Err("given cid doesn't match given record".into());
+    }
+    options.check_cbor("record", &cbor)?;
 
-    let mut blocks: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    if did.as_str() != did_doc.id {
+        return Err("record uri did doesn't match did doc id".into());
+    }
 
-    while let Some(block) = stream.next().await {
-        let (cid, cbor) = block.unwrap();
-        let (hash_type, hash_digest, hash_len) = cid.hash().into_inner();
-        if hash_type != 0x12 || hash_len != 0x20 {
-            return Err("unexpected cid type".into());
+    let pds = did_doc.get_pds()?;
+    let (roots, blocks) = fetch_record_car(
+        pds,
+        &did,
+        &collection,
+        &rkey,
+        options.car_limits(),
+        &fetch_options,
+    )
+    .await?;
+
+    let multibase_key = did_doc.get_signing_key()?;
+    let (_, signing_key) =
+        libipld::multibase::decode(multibase_key).map_err(|_| "couldn't decode signing key")?;
+
+    let key = format!("{}/{}", collection, rkey);
+    let mut found_in = None;
+
+    for root in roots {
+        let block_data = match blocks.get(&root.to_bytes()) {
+            Some(block) => block,
+            None => return Err("commit block missing from car".into()),
+        };
+        let root_object = verify_commit(block_data, did.as_str(), &signing_key)?;
+        options.check_cbor("commit", block_data)?;
+        options.check_rev(&root_object.rev)?;
+
+        let mut visited: HashSet<Cid> = HashSet::new();
+        mst::dfs(&blocks, &mut visited, Some(root_object.data), cid, None)?;
+        if found_in.is_some() {
+            continue;
         }
-
-        let record_hash = sha2::Sha256::digest(&cbor);
-
-        if &hash_digest[..32] != record_hash.as_slice() {
-            return Err("a cid in the car doesn't match its record".into());
+        // the record has to be under the requested key, not just anywhere in the tree
+        if let Ok((path, Some(value))) = mst::find_path(&blocks, root_object.data, &key) {
+            if value == cid {
+                let depth = path.len() as u32 - 1;
+                found_in = Some((root, root_object.rev, root_object.data, depth));
+            }
         }
-        blocks.insert(cid.to_bytes(), cbor);
     }
 
-    let signing_key = did_doc.get_signing_key()?;
-    let (_, signing_key) = libipld::multibase::decode(signing_key).unwrap();
+    let (commit_cid, rev, data, record_depth) = match found_in {
+        Some(found_in) => found_in,
+        None => return Err("could not find cid in signed roots".into()),
+    };
 
-    let mut visited: HashSet<Cid> = HashSet::new();
+    let record = if options.return_record {
+        match blocks.get(&cid.to_bytes()) {
+            Some(block) => Some(value::decode_cbor(block)?),
+            None => return Err("record block missing from car".into()),
+        }
+    } else {
+        None
+    };
 
-    let mut car_found = false;
+    let account_status = if options.check_account_status {
+        Some(account::AccountStatus::fetch(pds, &did, &fetch_options).await?)
+    } else {
+        None
+    };
+    let is_latest_commit = match &account_status {
+        Some(account_status) => account_status.is_latest(&rev, &commit_cid)?,
+        None => None,
+    };
 
-    for root in header.roots() {
-        let block_data = blocks.get(&root.to_bytes()).unwrap();
-        let root_object: SignedCommitObject = serde_ipld_dagcbor::from_slice(block_data).unwrap();
-        if root_object.did != parts[0] {
-            return Err("did from car doesn't match did from uri".into());
-        }
-        let unsigned_object = UnsignedCommitObject {
-            did: root_object.did,
-            version: root_object.version,
-            data: root_object.data,
-            rev: root_object.rev,
-            prev: root_object.prev,
-        };
-        let data_signed = serde_ipld_dagcbor::to_vec(&unsigned_object).unwrap();
+    // only once everything checked out, so a failed verification can't move
+    // the stored rev forward
+    rev_store::check_and_record(&did, &rev, &commit_cid.to_string()).await?;
+
+    value::serialize_js(&VerificationReport {
+        did: did.as_str(),
+        pds: Some(pds),
+        signing_key: multibase_key,
+        curve: key_curve(&signing_key),
+        commit_cid: commit_cid.to_string(),
+        rev,
+        data: data.to_string(),
+        record_depth,
+        blocks_checked: blocks.len(),
+        did_doc_source,
+        active: account_status.as_ref().map(|account| account.active),
+        status: account_status
+            .as_ref()
+            .and_then(|account| account.status.clone()),
+        latest_commit: account_status.and_then(|account| account.latest_commit),
+        is_latest_commit,
+        record: record.as_ref().map(value::Json),
+    })
+}
 
-        if root_object.sig.len() != 64 {
-            return Err("unexpected signature length".into());
-        }
-        let sc_r: [u8; 32] = root_object.sig[..32].try_into().unwrap();
-        let sc_s: [u8; 32] = root_object.sig[32..].try_into().unwrap();
-
-        let result = match signing_key[..2] {
-            [0xe7, 0x01] => {
-                let pub_key =
-                    k256::ecdsa::VerifyingKey::from_sec1_bytes(&signing_key[2..]).unwrap();
-                let signature = k256::ecdsa::Signature::from_scalars(sc_r, sc_s).unwrap();
-                pub_key.verify(&data_signed, &signature)
-            }
-            [0x80, 0x24] => {
-                let pub_key =
-                    p256::ecdsa::VerifyingKey::from_sec1_bytes(&signing_key[2..]).unwrap();
-                let signature = p256::ecdsa::Signature::from_scalars(sc_r, sc_s).unwrap();
-                pub_key.verify(&data_signed, &signature)
-            }
-            _ => {
-                return Err("unknown signing key format".into());
-            }
-        };
-        if result.is_err() {
-            return Err("signature not verified".into());
-        }
+/// Verifies that `record`, with cid `cid`, is the record at `uri` in the
+/// signed repo of the account whose did document is `did_doc`, and returns
+/// a verification report. Works for records of any collection.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_record_with_doc(
+    uri: &str,
+    cid: &str,
+    record: JsValue,
+    did_doc: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let options = VerifyOptions::from_js(options)?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
+    authenticate(uri, cid, record, did_doc, options, "provided").await
+}
 
-        let res = dfs(&blocks, &mut visited, Some(root_object.data), cid)?;
-        car_found = car_found || res.found;
-    }
+/// Like `verify_record_with_doc`, resolving the did document itself.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_record(
+    uri: &str,
+    cid: &str,
+    record: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let options = VerifyOptions::from_js(options)?;
+    let (did, _, _) = split_record_uri(uri)?;
+    let source = did_doc_url(&did)?;
+    let did_doc = get_did_doc(&did, &options.fetch_options()?).await?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
 
-    if !car_found {
-        return Err("could not find cid in signed roots".into());
-    }
+    authenticate(uri, cid, record, did_doc, options, &source).await
+}
 
-    Ok(())
+/// The original name of `verify_record_with_doc`, from when only posts were
+/// verified.
+#[wasm_bindgen(skip_typescript)]
+pub async fn authenticate_post_with_doc(
+    uri: &str,
+    cid: &str,
+    record: JsValue,
+    did_doc: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    verify_record_with_doc(uri, cid, record, did_doc, options).await
 }
 
-#[wasm_bindgen]
-pub async fn authenticate_post(uri: &str, cid: &str, record: JsValue) -> Result<(), JsValue> {
-    let parts: Vec<&str> = uri[5..].split('/').collect();
-    if parts.len() != 3 {
-        return Err("invalid record uri".into());
-    }
+/// The original name of `verify_record`.
+#[wasm_bindgen(skip_typescript)]
+pub async fn authenticate_post(
+    uri: &str,
+    cid: &str,
+    record: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    verify_record(uri, cid, record, options).await
+}
 
-    let did_doc = get_did_doc(parts[0]).await?;
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifiedRecord<'a> {
+    cid: String,
+    record: value::Json<'a>,
+    commit_rev: &'a str,
+    commit_cid: String,
+}
 
-    authenticate_post_with_doc(uri, cid, record, did_doc).await
+/// A record fetched from its PDS, along with the commit that vouches for it.
+struct FetchedRecord {
+    cid: Cid,
+    record: Value,
+    commit_rev: String,
+    commit_cid: Cid,
 }
 
-async fn get_did_doc(did: &str) -> Result<JsValue, JsValue> {
-    let url = match &did[..8] {
-        "did:plc:" => format!("https://plc.directory/{did}"),
-        "did:web:" => format!("https://{}/.well-known/did.json", &did[8..]),
-        _ => return Err("invalid did".into()),
+/// Fetches the record at `uri` straight from its PDS, failing unless its
+/// commit signature and MST path verify.
+async fn fetch_verified(uri: &str, options: &VerifyOptions) -> Result<FetchedRecord, JsValue> {
+    let (did, collection, rkey) = split_record_uri(uri)?;
+    let fetch_options = options.fetch_options()?;
+
+    let did_doc = get_did_doc(&did, &fetch_options).await?;
+    let did_doc: DidDocument = serde_wasm_bindgen::from_value(did_doc)?;
+    if did.as_str() != did_doc.id {
+        return Err("record uri did doesn't match did doc id".into());
+    }
+
+    let (roots, blocks) = fetch_record_car(
+        did_doc.get_pds()?,
+        &did,
+        &collection,
+        &rkey,
+        options.car_limits(),
+        &fetch_options,
+    )
+    .await?;
+
+    let commit_cid = match roots.first() {
+        Some(root) => *root,
+        None => return Err("car has no root".into()),
+    };
+    let commit_block = match blocks.get(&commit_cid.to_bytes()) {
+        Some(block) => block,
+        None => return Err("commit block missing from car".into()),
     };
 
-    let opts = RequestInit::new();
-    opts.set_method("GET");
-    opts.set_mode(RequestMode::Cors);
+    let signing_key = did_doc.get_signing_key()?;
+    let (_, signing_key) =
+        libipld::multibase::decode(signing_key).map_err(|_| "couldn't decode signing key")?;
+    let commit = verify_commit(commit_block, did.as_str(), &signing_key)?;
+    options.check_cbor("commit", commit_block)?;
+    options.check_rev(&commit.rev)?;
+
+    let (_, cid) = mst::find_path(&blocks, commit.data, &format!("{}/{}", collection, rkey))?;
+    let cid = match cid {
+        Some(cid) => cid,
+        None => return Err("could not find record in signed repo".into()),
+    };
+    mst::dfs(&blocks, &mut HashSet::new(), Some(commit.data), cid, None)?;
+    let record = match blocks.get(&cid.to_bytes()) {
+        Some(block) => {
+            options.check_cbor("record", block)?;
+            value::decode_cbor(block)?
+        }
+        None => return Err("record block missing from car".into()),
+    };
+    check_record_type(&collection, value_record_type(&record))?;
+    rev_store::check_and_record(&did, &commit.rev, &commit_cid.to_string()).await?;
+
+    Ok(FetchedRecord {
+        cid,
+        record,
+        commit_rev: commit.rev,
+        commit_cid,
+    })
+}
 
-    let window = web_sys::window().unwrap();
+/// Fetches the record at `uri` straight from its PDS and returns it only
+/// once its commit signature and MST path have been verified, as
+/// `{cid, record, commitRev, commitCid}`.
+#[wasm_bindgen(skip_typescript)]
+pub async fn fetch_verified_record(uri: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let options = VerifyOptions::from_js(options)?;
+    let fetched = fetch_verified(uri, &options).await?;
+
+    value::serialize_js(&VerifiedRecord {
+        cid: fetched.cid.to_string(),
+        record: value::Json(&fetched.record),
+        commit_rev: &fetched.commit_rev,
+        commit_cid: fetched.commit_cid.to_string(),
+    })
+}
 
-    let request = Request::new_with_str_and_init(&url, &opts)?;
+fn did_doc_url(did: &Did) -> Result<String, JsValue> {
+    Ok(match did.method() {
+        "plc" => format!("https://plc.directory/{did}"),
+        // atproto only allows hostname-level did:web, with an optional port
+        "web" if !did.identifier().contains(':') => format!(
+            "https://{}/.well-known/did.json",
+            did.identifier().replace("%3A", ":")
+        ),
+        _ => return Err("unsupported did method".into()),
+    })
+}
 
-    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-    if !resp_value.is_instance_of::<Response>() {
-        return Err("could not get response".into());
-    }
-    let resp: Response = resp_value.dyn_into().unwrap();
+async fn get_did_doc(did: &Did, fetch_options: &FetchOptions) -> Result<JsValue, JsValue> {
+    let url = did_doc_url(did)?;
+    let resp = fetch(&url, fetch_options).await?;
+    xrpc::expect_json(&url, &resp)?;
 
-    Ok(JsFuture::from(resp.json()?).await?)
+    resp.read(JsFuture::from(resp.json()?)).await
 }
 
+/// Installs the panic hook and logger. Entry points call it on load, so
+/// calling it again is a no-op.
 #[wasm_bindgen]
 pub fn init() {
     extern crate console_error_panic_hook;
 
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-    console_log::init_with_level(log::Level::Debug).unwrap();
-    log::info!("initialized with logging");
+    if console_log::init_with_level(log::Level::Debug).is_ok() {
+        log::info!("initialized with logging");
+    }
 }
@@ -0,0 +1,705 @@
+use crate::blockstore::BlockStore;
+use crate::syntax::{Nsid, RecordKey};
+use crate::{cid_for_cbor, hasher};
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use wasm_bindgen::JsValue;
+
+pub fn key_depth(key: &str) -> u32 {
+    let key_digest = sha2::Sha256::digest(key);
+    let mut zero_count = 0u32;
+    'count: for byte in key_digest {
+        for bit in (0..8u32).rev() {
+            if (byte >> bit & 1) != 0 {
+                break 'count;
+            }
+            zero_count += 1;
+        }
+    }
+    zero_count / 2
+}
+
+// fields are in canonical dag-cbor key order, so re-encoding a valid node
+// reproduces it byte for byte
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IPLDEntry {
+    k: serde_bytes::ByteBuf,
+    p: u32,
+    t: Option<Cid>,
+    v: Cid,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IPLDNode {
+    e: Vec<IPLDEntry>,
+    l: Option<Cid>,
+}
+
+/// The ways a tree can break the structural rules of the repository spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MstError {
+    InvalidNode,
+    NonCanonicalNode,
+    EmptyNode,
+    UntrimmedRoot,
+    NodeReused,
+    InvalidLink,
+    PrefixTooLong,
+    InvalidKey,
+    KeysOutOfOrder,
+    WrongLayer,
+}
+
+impl MstError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            MstError::InvalidNode => "node is not a valid mst node",
+            MstError::NonCanonicalNode => "node is not canonically encoded",
+            MstError::EmptyNode => "node below the root has no entries or subtree",
+            MstError::UntrimmedRoot => "root has a subtree but no entries",
+            MstError::NodeReused => "node appears more than once in the tree",
+            MstError::InvalidLink => "node links to something that isn't dag-cbor",
+            MstError::PrefixTooLong => "entry prefix is longer than the previous key",
+            MstError::InvalidKey => "key is not a valid collection/rkey pair",
+            MstError::KeysOutOfOrder => "keys are out of order",
+            MstError::WrongLayer => "key is on the wrong layer of the tree",
+        }
+    }
+}
+
+impl From<MstError> for JsValue {
+    fn from(err: MstError) -> JsValue {
+        format!("invalid mst: {}", err.message()).into()
+    }
+}
+
+/// Decodes a node, insisting that it is in the one encoding the tree's
+/// hashes are defined over.
+pub fn decode_node(block: &[u8]) -> Result<IPLDNode, MstError> {
+    let node: IPLDNode =
+        serde_ipld_dagcbor::from_slice(block).map_err(|_| MstError::InvalidNode)?;
+    match serde_ipld_dagcbor::to_vec(&node) {
+        Ok(encoded) if encoded == block => Ok(node),
+        _ => Err(MstError::NonCanonicalNode),
+    }
+}
+
+/// Checks that `key` is a `collection/rkey` pair of at most 1024 bytes.
+pub fn check_key(key: &str) -> Result<(), MstError> {
+    match key.split_once('/') {
+        Some((collection, rkey))
+            if key.len() <= 1024
+                && Nsid::from_str(collection).is_ok()
+                && RecordKey::from_str(rkey).is_ok() =>
+        {
+            Ok(())
+        }
+        _ => Err(MstError::InvalidKey),
+    }
+}
+
+/// Rebuilds the full key of `entry` from the key before it.
+fn entry_key(prev: &[u8], entry: &IPLDEntry) -> Result<String, MstError> {
+    let prefix = entry.p as usize;
+    if prefix > prev.len() {
+        return Err(MstError::PrefixTooLong);
+    }
+    let mut key = prev[..prefix].to_vec();
+    key.extend_from_slice(&entry.k);
+    let key = String::from_utf8(key).map_err(|_| MstError::InvalidKey)?;
+    check_key(&key)?;
+    Ok(key)
+}
+
+pub struct DFSState {
+    pub found: bool,
+    min: Option<String>,
+    max: Option<String>,
+}
+
+const NOT_FOUND: DFSState = DFSState {
+    found: false,
+    min: None,
+    max: None,
+};
+
+/// A decoded node with the full keys of its entries and its layer.
+struct CheckedNode {
+    node: IPLDNode,
+    keys: Vec<String>,
+    layer: u32,
+}
+
+/// Decodes a node and checks it against every rule that doesn't involve any
+/// other node. `layer` is the layer the node must be on, or `None` for the
+/// root, which is on the layer of its keys. An empty root gives `None`.
+fn check_node(block: &[u8], layer: Option<u32>) -> Result<Option<CheckedNode>, MstError> {
+    let node = decode_node(block)?;
+
+    let links = node
+        .e
+        .iter()
+        .flat_map(|entry| entry.t.iter().chain([&entry.v]));
+    if node
+        .l
+        .iter()
+        .chain(links)
+        .any(|cid| cid.codec() != hasher::DAG_CBOR)
+    {
+        return Err(MstError::InvalidLink);
+    }
+
+    let mut keys: Vec<String> = Vec::with_capacity(node.e.len());
+    for entry in node.e.iter() {
+        let prev = keys.last().map_or(&[][..], |key| key.as_bytes());
+        let key = entry_key(prev, entry)?;
+        keys.push(key);
+    }
+    if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(MstError::KeysOutOfOrder);
+    }
+
+    let layer = match (layer, keys.first()) {
+        (Some(layer), _) => layer,
+        (None, Some(key)) => key_depth(key),
+        (None, None) if node.l.is_none() => return Ok(None),
+        (None, None) => return Err(MstError::UntrimmedRoot),
+    };
+    if keys.is_empty() && node.l.is_none() {
+        return Err(MstError::EmptyNode);
+    }
+    if keys.iter().any(|key| key_depth(key) != layer) {
+        return Err(MstError::WrongLayer);
+    }
+    let has_subtrees = node.l.is_some() || node.e.iter().any(|entry| entry.t.is_some());
+    if layer == 0 && has_subtrees {
+        return Err(MstError::WrongLayer);
+    }
+
+    Ok(Some(CheckedNode { node, keys, layer }))
+}
+
+/// Checks the subtree at `start` against every structural rule and looks for
+/// `target` among its values. `layer` is the layer the node must be on, or
+/// `None` for the root. Nodes missing from `tree` are skipped, since proofs
+/// only carry the path to one record.
+pub fn dfs(
+    tree: &HashMap<Vec<u8>, Vec<u8>>,
+    visited: &mut HashSet<Cid>,
+    start: Option<Cid>,
+    target: Cid,
+    layer: Option<u32>,
+) -> Result<DFSState, MstError> {
+    let start = match start {
+        Some(cid) => cid,
+        None => return Ok(NOT_FOUND),
+    };
+    if !visited.insert(start) {
+        return Err(MstError::NodeReused);
+    }
+    let block = match tree.get(&start.to_bytes()) {
+        Some(block) => block,
+        None => return Ok(NOT_FOUND),
+    };
+    let CheckedNode { node, keys, layer } = match check_node(block, layer)? {
+        Some(checked) => checked,
+        None => return Ok(NOT_FOUND),
+    };
+    let child_layer = Some(layer.saturating_sub(1));
+
+    let left = dfs(tree, visited, node.l, target, child_layer)?;
+    let mut found = left.found || node.e.iter().any(|entry| entry.v == target);
+    if let (Some(max), Some(first)) = (&left.max, keys.first()) {
+        if max >= first {
+            return Err(MstError::KeysOutOfOrder);
+        }
+    }
+
+    let min = left.min.or_else(|| keys.first().cloned());
+    let mut max = left.max;
+    for (i, entry) in node.e.iter().enumerate() {
+        let right = dfs(tree, visited, entry.t, target, child_layer)?;
+        if right
+            .min
+            .as_ref()
+            .is_some_and(|right_min| right_min <= &keys[i])
+        {
+            return Err(MstError::KeysOutOfOrder);
+        }
+        if let (Some(right_max), Some(next)) = (&right.max, keys.get(i + 1)) {
+            if right_max >= next {
+                return Err(MstError::KeysOutOfOrder);
+            }
+        }
+        found = found || right.found;
+        max = right.max.or_else(|| Some(keys[i].clone()));
+    }
+    Ok(DFSState { found, min, max })
+}
+
+/// An in-memory Merkle Search Tree, rebuilt into canonical node blocks on
+/// demand.
+#[derive(Default)]
+pub struct Mst {
+    entries: BTreeMap<String, Cid>,
+}
+
+impl Mst {
+    pub fn new() -> Mst {
+        Mst::default()
+    }
+
+    pub fn insert(&mut self, key: String, value: Cid) -> Option<Cid> {
+        self.entries.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Cid> {
+        self.entries.remove(key)
+    }
+
+    pub fn values(&self) -> Vec<Cid> {
+        self.entries.values().copied().collect()
+    }
+
+    /// Returns the root cid and every node block of the tree.
+    pub fn build(&self) -> Result<(Cid, HashMap<Vec<u8>, Vec<u8>>), JsValue> {
+        let entries: Vec<(&str, Cid, u32)> = self
+            .entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), *value, key_depth(key)))
+            .collect();
+        let layer = entries.iter().map(|entry| entry.2).max().unwrap_or(0);
+
+        let mut blocks = HashMap::new();
+        let root = build_node(&entries, layer, &mut blocks)?;
+        Ok((root, blocks))
+    }
+}
+
+fn build_node(
+    entries: &[(&str, Cid, u32)],
+    layer: u32,
+    blocks: &mut HashMap<Vec<u8>, Vec<u8>>,
+) -> Result<Cid, JsValue> {
+    let mut node = IPLDNode {
+        l: None,
+        e: Vec::new(),
+    };
+    let mut prev_key = "";
+    let mut start = 0;
+
+    for i in 0..=entries.len() {
+        if i < entries.len() && entries[i].2 < layer {
+            continue;
+        }
+
+        // everything between two keys of this layer goes into a subtree one layer down
+        let subtree = if start < i {
+            Some(build_node(&entries[start..i], layer - 1, blocks)?)
+        } else {
+            None
+        };
+        match node.e.last_mut() {
+            Some(last) => last.t = subtree,
+            None => node.l = subtree,
+        }
+
+        if i < entries.len() {
+            let (key, value, _) = entries[i];
+            let prefix = prev_key
+                .bytes()
+                .zip(key.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            node.e.push(IPLDEntry {
+                p: prefix as u32,
+                k: serde_bytes::ByteBuf::from(&key.as_bytes()[prefix..]),
+                v: value,
+                t: None,
+            });
+            prev_key = key;
+        }
+        start = i + 1;
+    }
+
+    let block = serde_ipld_dagcbor::to_vec(&node).map_err(|_| "couldn't encode mst node")?;
+    let cid = cid_for_cbor(&block);
+    blocks.insert(cid.to_bytes(), block);
+    Ok(cid)
+}
+
+/// A node on the way to a key, with the layer it must be on (`None` for the
+/// root) and the keys its own keys must lie strictly between.
+struct Bounded {
+    cid: Cid,
+    layer: Option<u32>,
+    lower: Option<String>,
+    upper: Option<String>,
+}
+
+impl Bounded {
+    fn root(cid: Cid) -> Bounded {
+        Bounded {
+            cid,
+            layer: None,
+            lower: None,
+            upper: None,
+        }
+    }
+}
+
+enum Step {
+    Found(Cid),
+    Next(Option<Bounded>),
+}
+
+/// Checks the node `at` against the rules that can be checked without its
+/// siblings, and takes one step from it towards `key`.
+fn step(block: &[u8], key: &str, at: Bounded) -> Result<Step, JsValue> {
+    let CheckedNode { node, keys, layer } = match check_node(block, at.layer)? {
+        Some(checked) => checked,
+        None => return Ok(Step::Next(None)),
+    };
+    let below_lower = at
+        .lower
+        .as_ref()
+        .is_some_and(|lower| keys.first().is_some_and(|first| first <= lower));
+    let above_upper = at
+        .upper
+        .as_ref()
+        .is_some_and(|upper| keys.last().is_some_and(|last| last >= upper));
+    if below_lower || above_upper {
+        return Err(MstError::KeysOutOfOrder.into());
+    }
+
+    let i = keys.partition_point(|other| other.as_str() < key);
+    if keys.get(i).is_some_and(|other| other == key) {
+        return Ok(Step::Found(node.e[i].v));
+    }
+    let (next, lower) = match i {
+        0 => (node.l, at.lower),
+        _ => (node.e[i - 1].t, Some(keys[i - 1].clone())),
+    };
+    let upper = keys.get(i).cloned().or(at.upper);
+    Ok(Step::Next(next.map(|cid| Bounded {
+        cid,
+        layer: Some(layer.saturating_sub(1)),
+        lower,
+        upper,
+    })))
+}
+
+/// Walks from `root` towards `key`, returning the cids of every node on the
+/// way and the value stored under `key`, if any. Every node on the way is
+/// checked on its own and against the keys above it.
+pub fn find_path(
+    tree: &HashMap<Vec<u8>, Vec<u8>>,
+    root: Cid,
+    key: &str,
+) -> Result<(Vec<Cid>, Option<Cid>), JsValue> {
+    let mut path = Vec::new();
+    let mut next = Some(Bounded::root(root));
+
+    while let Some(at) = next {
+        let block = match tree.get(&at.cid.to_bytes()) {
+            Some(block) => block,
+            None => return Err("mst node missing from tree".into()),
+        };
+        path.push(at.cid);
+        next = match step(block, key, at)? {
+            Step::Found(value) => return Ok((path, Some(value))),
+            Step::Next(next) => next,
+        };
+    }
+
+    Ok((path, None))
+}
+
+/// Like `find_path`, but only fetches the nodes on the path from `store`.
+pub async fn lookup<S: BlockStore>(
+    store: &S,
+    root: Cid,
+    key: &str,
+) -> Result<(Vec<Cid>, Option<Cid>), JsValue> {
+    let mut path = Vec::new();
+    let mut next = Some(Bounded::root(root));
+
+    while let Some(at) = next {
+        let block = match store.get_block(&at.cid).await? {
+            Some(block) => block,
+            None => return Err("mst node missing from tree".into()),
+        };
+        path.push(at.cid);
+        next = match step(&block, key, at)? {
+            Step::Found(value) => return Ok((path, Some(value))),
+            Step::Next(next) => next,
+        };
+    }
+
+    Ok((path, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn key(n: u32) -> String {
+        format!("com.example.record/a{:03}", n)
+    }
+
+    fn record() -> Cid {
+        cid_for_cbor(&serde_ipld_dagcbor::to_vec("record").unwrap())
+    }
+
+    #[derive(Default)]
+    struct Tree(HashMap<Vec<u8>, Vec<u8>>);
+
+    impl Tree {
+        fn block(&mut self, block: Vec<u8>) -> Cid {
+            let cid = cid_for_cbor(&block);
+            self.0.insert(cid.to_bytes(), block);
+            cid
+        }
+
+        fn raw(&mut self, node: &IPLDNode) -> Cid {
+            self.block(serde_ipld_dagcbor::to_vec(node).unwrap())
+        }
+
+        /// A node with the given keys, prefix-compressed the usual way.
+        fn node(&mut self, l: Option<Cid>, entries: &[(u32, Option<Cid>)]) -> Cid {
+            let mut node = IPLDNode { l, e: Vec::new() };
+            let mut prev = String::new();
+            for (n, t) in entries {
+                let key = key(*n);
+                let p = prev
+                    .bytes()
+                    .zip(key.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                node.e.push(IPLDEntry {
+                    p: p as u32,
+                    k: serde_bytes::ByteBuf::from(&key.as_bytes()[p..]),
+                    v: record(),
+                    t: *t,
+                });
+                prev = key;
+            }
+            self.raw(&node)
+        }
+
+        fn entry(&mut self, p: u32, k: &[u8]) -> Cid {
+            self.raw(&IPLDNode {
+                l: None,
+                e: vec![IPLDEntry {
+                    p,
+                    k: serde_bytes::ByteBuf::from(k),
+                    v: record(),
+                    t: None,
+                }],
+            })
+        }
+
+        fn check(&self, root: Cid) -> Result<bool, MstError> {
+            let state = dfs(&self.0, &mut HashSet::new(), Some(root), record(), None)?;
+            Ok(state.found)
+        }
+
+        /// Looks `key(n)` up from `root`, checking only the nodes on the way.
+        fn find(&self, root: Cid, n: u32) -> Result<Option<Cid>, JsValue> {
+            Ok(find_path(&self.0, root, &key(n))?.1)
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn depths() {
+        let depths: Vec<u32> = (0..12).map(|n| key_depth(&key(n))).collect();
+        assert_eq!(depths, [2, 0, 2, 0, 0, 0, 0, 0, 1, 0, 0, 1]);
+    }
+
+    #[wasm_bindgen_test]
+    fn valid_trees() {
+        let mut tree = Tree::default();
+        let left = tree.node(None, &[(1, None), (3, None)]);
+        let right = tree.node(None, &[(9, None)]);
+        let root = tree.node(Some(left), &[(8, Some(right))]);
+        assert_eq!(tree.check(root), Ok(true));
+
+        let mut mst = Mst::new();
+        for n in 0..12 {
+            mst.insert(key(n), record());
+        }
+        let (root, blocks) = mst.build().unwrap();
+        assert_eq!(Tree(blocks).check(root), Ok(true));
+
+        let (root, blocks) = Mst::new().build().unwrap();
+        assert_eq!(Tree(blocks).check(root), Ok(false));
+    }
+
+    #[wasm_bindgen_test]
+    fn missing_nodes_are_not_errors() {
+        let mut tree = Tree::default();
+        let left = Tree::default().node(None, &[(1, None)]);
+        let root = tree.node(Some(left), &[(8, None)]);
+        assert_eq!(tree.check(root), Ok(true));
+    }
+
+    #[wasm_bindgen_test]
+    fn malformed_nodes() {
+        let mut tree = Tree::default();
+        let root = tree.block(serde_ipld_dagcbor::to_vec("not a node").unwrap());
+        assert_eq!(tree.check(root), Err(MstError::InvalidNode));
+
+        #[derive(Serialize)]
+        struct ShortEntry {
+            k: serde_bytes::ByteBuf,
+            p: u32,
+            v: Cid,
+        }
+        #[derive(Serialize)]
+        struct ShortNode {
+            e: Vec<ShortEntry>,
+            l: Option<Cid>,
+        }
+        let root = tree.block(
+            serde_ipld_dagcbor::to_vec(&ShortNode {
+                l: None,
+                e: vec![ShortEntry {
+                    p: 0,
+                    k: serde_bytes::ByteBuf::from(key(1).into_bytes()),
+                    v: record(),
+                }],
+            })
+            .unwrap(),
+        );
+        assert_eq!(tree.check(root), Err(MstError::NonCanonicalNode));
+
+        let raw = hasher::cid_for(hasher::RAW, b"record");
+        let root = tree.raw(&IPLDNode {
+            l: None,
+            e: vec![IPLDEntry {
+                p: 0,
+                k: serde_bytes::ByteBuf::from(key(1).into_bytes()),
+                v: raw,
+                t: None,
+            }],
+        });
+        assert_eq!(tree.check(root), Err(MstError::InvalidLink));
+    }
+
+    #[wasm_bindgen_test]
+    fn empty_and_untrimmed_nodes() {
+        let mut tree = Tree::default();
+        let empty = tree.node(None, &[]);
+        let root = tree.node(Some(empty), &[(8, None)]);
+        assert_eq!(tree.check(root), Err(MstError::EmptyNode));
+
+        let mut tree = Tree::default();
+        let leaf = tree.node(None, &[(1, None)]);
+        let root = tree.node(Some(leaf), &[]);
+        assert_eq!(tree.check(root), Err(MstError::UntrimmedRoot));
+    }
+
+    #[wasm_bindgen_test]
+    fn reused_nodes() {
+        let mut tree = Tree::default();
+        let leaf = tree.node(None, &[(1, None)]);
+        let root = tree.node(Some(leaf), &[(8, Some(leaf))]);
+        assert_eq!(tree.check(root), Err(MstError::NodeReused));
+    }
+
+    #[wasm_bindgen_test]
+    fn bad_keys() {
+        let mut tree = Tree::default();
+        let root = tree.entry(3, key(1).as_bytes());
+        assert_eq!(tree.check(root), Err(MstError::PrefixTooLong));
+
+        let root = tree.entry(0, b"com.example.record/\xff");
+        assert_eq!(tree.check(root), Err(MstError::InvalidKey));
+
+        let root = tree.entry(0, b"com.example.record");
+        assert_eq!(tree.check(root), Err(MstError::InvalidKey));
+
+        let root = tree.entry(0, b"com.example.record/..");
+        assert_eq!(tree.check(root), Err(MstError::InvalidKey));
+    }
+
+    #[wasm_bindgen_test]
+    fn keys_out_of_order() {
+        let mut tree = Tree::default();
+        let root = tree.node(None, &[(3, None), (1, None)]);
+        assert_eq!(tree.check(root), Err(MstError::KeysOutOfOrder));
+
+        let root = tree.node(None, &[(1, None), (1, None)]);
+        assert_eq!(tree.check(root), Err(MstError::KeysOutOfOrder));
+
+        let left = tree.node(None, &[(9, None)]);
+        let root = tree.node(Some(left), &[(8, None)]);
+        assert_eq!(tree.check(root), Err(MstError::KeysOutOfOrder));
+
+        let right = tree.node(None, &[(1, None)]);
+        let root = tree.node(None, &[(8, Some(right))]);
+        assert_eq!(tree.check(root), Err(MstError::KeysOutOfOrder));
+
+        let right = tree.node(None, &[(9, None)]);
+        let root = tree.node(None, &[(8, Some(right)), (11, None)]);
+        assert_eq!(tree.check(root), Ok(true));
+        let right = tree.node(None, &[(12, None)]);
+        let root = tree.node(None, &[(8, Some(right)), (11, None)]);
+        assert_eq!(tree.check(root), Err(MstError::KeysOutOfOrder));
+    }
+
+    #[wasm_bindgen_test]
+    fn wrong_layers() {
+        let mut tree = Tree::default();
+        let root = tree.node(None, &[(1, None), (8, None)]);
+        assert_eq!(tree.check(root), Err(MstError::WrongLayer));
+
+        let right = tree.node(None, &[(11, None)]);
+        let root = tree.node(None, &[(8, Some(right))]);
+        assert_eq!(tree.check(root), Err(MstError::WrongLayer));
+
+        let left = tree.node(None, &[(1, None)]);
+        let root = tree.node(Some(left), &[(3, None)]);
+        assert_eq!(tree.check(root), Err(MstError::WrongLayer));
+
+        let leaf = tree.node(None, &[(4, None)]);
+        let root = tree.node(None, &[(0, Some(leaf))]);
+        assert_eq!(tree.check(root), Err(MstError::WrongLayer));
+    }
+
+    #[wasm_bindgen_test]
+    fn lookups_check_the_path() {
+        let mut tree = Tree::default();
+        let left = tree.node(None, &[(1, None), (3, None)]);
+        let right = tree.node(None, &[(9, None)]);
+        let root = tree.node(Some(left), &[(8, Some(right))]);
+        assert_eq!(tree.find(root, 3), Ok(Some(record())));
+        assert_eq!(tree.find(root, 9), Ok(Some(record())));
+        assert_eq!(tree.find(root, 10), Ok(None));
+
+        let invalid = |err: MstError| Err(JsValue::from(err));
+
+        let root = tree.node(None, &[(1, None), (8, None)]);
+        assert_eq!(tree.find(root, 8), invalid(MstError::WrongLayer));
+
+        let left = tree.node(None, &[(3, None), (1, None)]);
+        let root = tree.node(Some(left), &[(8, None)]);
+        assert_eq!(tree.find(root, 1), invalid(MstError::KeysOutOfOrder));
+
+        let empty = tree.node(None, &[]);
+        let root = tree.node(Some(empty), &[(8, None)]);
+        assert_eq!(tree.find(root, 1), invalid(MstError::EmptyNode));
+
+        let left = tree.node(None, &[(9, None)]);
+        let root = tree.node(Some(left), &[(8, None)]);
+        assert_eq!(tree.find(root, 7), invalid(MstError::KeysOutOfOrder));
+
+        let right = tree.node(None, &[(12, None)]);
+        let root = tree.node(None, &[(8, Some(right)), (11, None)]);
+        assert_eq!(tree.find(root, 9), invalid(MstError::KeysOutOfOrder));
+    }
+}
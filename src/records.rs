@@ -0,0 +1,67 @@
+use crate::syntax::Did;
+use crate::{split_record_uri, verify_record};
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+pub const POST: &str = "app.bsky.feed.post";
+pub const LIKE: &str = "app.bsky.feed.like";
+pub const REPOST: &str = "app.bsky.feed.repost";
+pub const FOLLOW: &str = "app.bsky.graph.follow";
+pub const PROFILE: &str = "app.bsky.actor.profile";
+pub const LIST: &str = "app.bsky.graph.list";
+pub const FEED_GENERATOR: &str = "app.bsky.feed.generator";
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_RECORDS: &str = r#"
+export function verify_post(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_like(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_repost(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_follow(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_list(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_feed_generator(uri: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+export function verify_profile(did: string, cid: string, record: AtprotoRecord, options?: VerifyOptions): Promise<VerificationReport>;
+"#;
+
+fn expect_collection(uri: &str, expected: &str) -> Result<(), JsValue> {
+    let (_, collection, _) = split_record_uri(uri)?;
+    if collection.as_str() != expected {
+        return Err(format!("expected a {} record, got {}", expected, collection).into());
+    }
+    Ok(())
+}
+
+macro_rules! typed_verifier {
+    ($name:ident, $collection:expr, $what:literal) => {
+        #[doc = concat!("`verify_record` for ", $what, ", failing if `uri` is in another collection.")]
+        #[wasm_bindgen(skip_typescript)]
+        pub async fn $name(
+            uri: &str,
+            cid: &str,
+            record: JsValue,
+            options: JsValue,
+        ) -> Result<JsValue, JsValue> {
+            expect_collection(uri, $collection)?;
+            verify_record(uri, cid, record, options).await
+        }
+    };
+}
+
+typed_verifier!(verify_post, POST, "posts");
+typed_verifier!(verify_like, LIKE, "likes");
+typed_verifier!(verify_repost, REPOST, "reposts");
+typed_verifier!(verify_follow, FOLLOW, "follows");
+typed_verifier!(verify_list, LIST, "lists");
+typed_verifier!(verify_feed_generator, FEED_GENERATOR, "feed generators");
+
+/// Verifies the profile of `did`, which always lives at the `self` rkey.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_profile(
+    did: &str,
+    cid: &str,
+    record: JsValue,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let did = Did::from_str(did)?;
+    let uri = format!("at://{}/{}/self", did, PROFILE);
+    verify_record(&uri, cid, record, options).await
+}
@@ -0,0 +1,254 @@
+use crate::car;
+use crate::mst::{self, Mst};
+use crate::syntax::{Did, Nsid, RecordKey, Tid};
+use crate::{cid_for_cbor, record_to_cbor, UnsignedCommitObject};
+use cid::Cid;
+use k256::ecdsa::signature::Signer;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+pub enum SigningKey {
+    K256(k256::ecdsa::SigningKey),
+    P256(p256::ecdsa::SigningKey),
+}
+
+impl SigningKey {
+    pub fn from_bytes(curve: &str, bytes: &[u8]) -> Result<SigningKey, JsValue> {
+        match curve {
+            "k256" | "secp256k1" => k256::ecdsa::SigningKey::from_slice(bytes)
+                .map(SigningKey::K256)
+                .map_err(|_| "invalid k256 private key".into()),
+            "p256" | "secp256r1" => p256::ecdsa::SigningKey::from_slice(bytes)
+                .map(SigningKey::P256)
+                .map_err(|_| "invalid p256 private key".into()),
+            _ => Err("unknown curve".into()),
+        }
+    }
+
+    /// The public key in the multikey format used by `publicKeyMultibase`.
+    pub fn public_key_multibase(&self) -> String {
+        let mut bytes = Vec::new();
+        match self {
+            SigningKey::K256(key) => {
+                bytes.extend_from_slice(&[0xe7, 0x01]);
+                bytes.extend_from_slice(key.verifying_key().to_encoded_point(true).as_bytes());
+            }
+            SigningKey::P256(key) => {
+                bytes.extend_from_slice(&[0x80, 0x24]);
+                bytes.extend_from_slice(key.verifying_key().to_encoded_point(true).as_bytes());
+            }
+        }
+        libipld::multibase::encode(libipld::multibase::Base::Base58Btc, bytes)
+    }
+
+    /// Signs the sha256 of `data` with a deterministic (RFC6979), low-S
+    /// signature and returns it in the 64 byte compact form.
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            SigningKey::K256(key) => {
+                let signature: k256::ecdsa::Signature = key.sign(data);
+                signature.normalize_s().unwrap_or(signature).to_bytes().to_vec()
+            }
+            SigningKey::P256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                signature.normalize_s().unwrap_or(signature).to_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// Fields are in canonical DAG-CBOR order (shorter keys first), since they
+/// are encoded in declaration order.
+#[derive(Serialize)]
+struct SignedCommitBlock<'a> {
+    did: &'a str,
+    rev: &'a str,
+    sig: &'a serde_bytes::Bytes,
+    data: Cid,
+    prev: Option<Cid>,
+    version: u16,
+}
+
+/// Signs `commit` and returns the cid and bytes of the signed commit block.
+pub fn sign_commit(
+    commit: &UnsignedCommitObject,
+    key: &SigningKey,
+) -> Result<(Cid, Vec<u8>), JsValue> {
+    let data_signed = serde_ipld_dagcbor::to_vec(commit).map_err(|_| "couldn't encode commit")?;
+    let sig = key.sign(&data_signed);
+
+    let signed = SignedCommitBlock {
+        did: &commit.did,
+        rev: &commit.rev,
+        sig: serde_bytes::Bytes::new(&sig),
+        data: commit.data,
+        prev: commit.prev,
+        version: commit.version,
+    };
+    let block = serde_ipld_dagcbor::to_vec(&signed).map_err(|_| "couldn't encode commit")?;
+    Ok((cid_for_cbor(&block), block))
+}
+
+/// The MST key for a record, checking both halves.
+pub fn record_key(collection: &str, rkey: &str) -> Result<String, JsValue> {
+    Ok(format!(
+        "{}/{}",
+        Nsid::from_str(collection)?,
+        RecordKey::from_str(rkey)?
+    ))
+}
+
+struct Commit {
+    cid: Cid,
+    block: Vec<u8>,
+    nodes: HashMap<Vec<u8>, Vec<u8>>,
+    records: Vec<Cid>,
+    data: Cid,
+}
+
+/// Write side of a repository: holds records, builds the MST and signs
+/// commits over it.
+#[wasm_bindgen]
+pub struct RepoWriter {
+    did: Did,
+    key: SigningKey,
+    mst: Mst,
+    records: HashMap<Vec<u8>, Vec<u8>>,
+    commit: Option<Commit>,
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_REPO_WRITER: &str = r#"
+export interface RepoWriter {
+  put_record(collection: string, rkey: string, record: AtprotoRecord): string;
+}
+"#;
+
+#[wasm_bindgen]
+impl RepoWriter {
+    #[wasm_bindgen(constructor)]
+    pub fn new(did: &str, curve: &str, private_key: &[u8]) -> Result<RepoWriter, JsValue> {
+        Ok(RepoWriter {
+            did: Did::from_str(did)?,
+            key: SigningKey::from_bytes(curve, private_key)?,
+            mst: Mst::new(),
+            records: HashMap::new(),
+            commit: None,
+        })
+    }
+
+    pub fn public_key_multibase(&self) -> String {
+        self.key.public_key_multibase()
+    }
+
+    /// Stores `record` under `collection/rkey` and returns its cid. The
+    /// change is only signed by the next `commit`.
+    #[wasm_bindgen(skip_typescript)]
+    pub fn put_record(
+        &mut self,
+        collection: &str,
+        rkey: &str,
+        record: JsValue,
+    ) -> Result<String, JsValue> {
+        let key = record_key(collection, rkey)?;
+        let cbor = record_to_cbor(record, true)?;
+        let cid = cid_for_cbor(&cbor);
+        self.records.insert(cid.to_bytes(), cbor);
+        self.mst.insert(key, cid);
+        Ok(cid.to_string())
+    }
+
+    pub fn delete_record(&mut self, collection: &str, rkey: &str) -> Result<bool, JsValue> {
+        Ok(self.mst.remove(&record_key(collection, rkey)?).is_some())
+    }
+
+    /// Signs a commit over the current tree and returns its cid.
+    pub fn commit(&mut self, rev: &str) -> Result<String, JsValue> {
+        let rev = Tid::from_str(rev)?;
+        let (data, nodes) = self.mst.build()?;
+        let unsigned = UnsignedCommitObject {
+            did: self.did.to_string(),
+            rev: rev.to_string(),
+            data,
+            prev: None,
+            version: 3,
+        };
+        let (cid, block) = sign_commit(&unsigned, &self.key)?;
+        self.commit = Some(Commit {
+            cid,
+            block,
+            nodes,
+            records: self.mst.values(),
+            data,
+        });
+        Ok(cid.to_string())
+    }
+
+    /// Returns a CAR in the shape of `com.atproto.sync.getRecord`: the
+    /// latest commit, the MST nodes down to the record and the record itself.
+    pub fn get_record_proof(&self, collection: &str, rkey: &str) -> Result<Vec<u8>, JsValue> {
+        let commit = match self.commit {
+            Some(ref commit) => commit,
+            None => return Err("repo has no commit yet".into()),
+        };
+
+        let (path, value) =
+            mst::find_path(&commit.nodes, commit.data, &record_key(collection, rkey)?)?;
+
+        let mut blocks: Vec<(Cid, &[u8])> = vec![(commit.cid, commit.block.as_slice())];
+        for cid in path {
+            blocks.push((cid, commit.nodes[&cid.to_bytes()].as_slice()));
+        }
+        if let Some(cid) = value {
+            blocks.push((cid, self.records[&cid.to_bytes()].as_slice()));
+        }
+
+        car::write_car(&[commit.cid], &blocks)
+    }
+
+    /// Returns the whole repository as a CAR, in the shape of
+    /// `com.atproto.sync.getRepo`.
+    pub fn export_repo(&self) -> Result<Vec<u8>, JsValue> {
+        let commit = match self.commit {
+            Some(ref commit) => commit,
+            None => return Err("repo has no commit yet".into()),
+        };
+
+        let mut writer = car::CarWriter::new(&[commit.cid])?;
+        writer.write(&commit.cid, &commit.block);
+        for (cid, block) in commit.nodes.iter() {
+            writer.write(&Cid::try_from(cid.as_slice()).unwrap(), block);
+        }
+        let mut written = HashSet::new();
+        for cid in commit.records.iter() {
+            if written.insert(*cid) {
+                writer.write(cid, &self.records[&cid.to_bytes()]);
+            }
+        }
+        Ok(writer.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Verifier;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn p256_signatures_are_low_s() {
+        let key = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let verifying_key = *key.verifying_key();
+        let key = SigningKey::P256(key);
+        // about half of these come out high-S before normalizing
+        for i in 0..64u8 {
+            let data = [i; 16];
+            let sig = p256::ecdsa::Signature::from_slice(&key.sign(&data)).unwrap();
+            assert!(sig.normalize_s().is_none());
+            verifying_key.verify(&data, &sig).unwrap();
+        }
+    }
+}
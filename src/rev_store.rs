@@ -0,0 +1,407 @@
+use crate::syntax::{Did, Tid};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{self, Function, Promise, Reflect};
+use web_sys::{
+    IdbDatabase, IdbFactory, IdbObjectStore, IdbRequest, IdbTransaction, IdbTransactionMode,
+};
+
+/// The newest commit verified for a repo.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RevMark {
+    pub rev: String,
+    pub cid: String,
+}
+
+/// Somewhere the highest verified rev of each repo is remembered.
+pub trait RevStore {
+    fn get<'a>(&'a self, did: &'a str) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>>;
+
+    /// Stores `mark` unless the stored rev is at least as new, and returns the
+    /// mark stored before. The comparison and the write happen in one step,
+    /// so concurrent verifications can never lower the stored rev.
+    fn put<'a>(
+        &'a self,
+        did: &'a str,
+        mark: RevMark,
+    ) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>>;
+}
+
+/// Whether storing `mark` over `stored` raises the high-water mark. A stored
+/// rev that isn't a valid tid is replaced.
+fn raises(stored: Option<&RevMark>, mark: &RevMark) -> bool {
+    match stored.and_then(|stored| Tid::from_str(&stored.rev).ok()) {
+        Some(seen) => Tid::from_str(&mark.rev).is_ok_and(|rev| rev > seen),
+        None => true,
+    }
+}
+
+fn mark_from_js(mark: JsValue) -> Result<Option<RevMark>, JsValue> {
+    if mark.is_undefined() || mark.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(serde_wasm_bindgen::from_value(mark)?))
+}
+
+#[derive(Default)]
+pub struct MemoryRevStore(RefCell<HashMap<String, RevMark>>);
+
+impl RevStore for MemoryRevStore {
+    fn get<'a>(&'a self, did: &'a str) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        let mark = self.0.borrow().get(did).cloned();
+        async move { Ok(mark) }.boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        did: &'a str,
+        mark: RevMark,
+    ) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        let mut marks = self.0.borrow_mut();
+        let stored = marks.get(did).cloned();
+        if raises(stored.as_ref(), &mark) {
+            marks.insert(did.to_owned(), mark);
+        }
+        async move { Ok(stored) }.boxed_local()
+    }
+}
+
+const OBJECT_STORE: &str = "revs";
+
+/// Resolves once `request` succeeds, with its result.
+fn request_done(request: &IdbRequest) -> JsFuture {
+    request_then(request, Ok)
+}
+
+/// Resolves with `then` of the result once `request` succeeds. `then` runs
+/// in the success handler, while the request's transaction is still active,
+/// so it can make further requests in it.
+fn request_then(
+    request: &IdbRequest,
+    then: impl FnOnce(JsValue) -> Result<JsValue, JsValue> + 'static,
+) -> JsFuture {
+    let mut then = Some(then);
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let success = request.clone();
+        let then = then.take().unwrap();
+        let onsuccess = Closure::once_into_js(move || {
+            let _ = match then(success.result().unwrap_or_default()) {
+                Ok(result) => resolve.call1(&JsValue::NULL, &result),
+                Err(err) => reject.call1(&JsValue::NULL, &err),
+            };
+        });
+        let failure = request.clone();
+        let onerror = Closure::once_into_js(move || {
+            let error = failure.error().ok().flatten().map(JsValue::from);
+            let _ = reject.call1(&JsValue::NULL, &error.unwrap_or_default());
+        });
+        request.set_onsuccess(Some(onsuccess.unchecked_ref()));
+        request.set_onerror(Some(onerror.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
+
+/// Resolves once `transaction` has committed.
+fn transaction_done(transaction: &IdbTransaction) -> JsFuture {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let oncomplete = Closure::once_into_js(move || {
+            let _ = resolve.call0(&JsValue::NULL);
+        });
+        let onabort = Closure::once_into_js(move || {
+            let _ = reject.call1(&JsValue::NULL, &"couldn't save verified rev".into());
+        });
+        transaction.set_oncomplete(Some(oncomplete.unchecked_ref()));
+        transaction.set_onabort(Some(onabort.unchecked_ref()));
+    });
+    JsFuture::from(promise)
+}
+
+/// Writes `mark` to `store` if it raises the `stored` one, and passes
+/// `stored` on.
+fn put_if_raised(
+    store: &IdbObjectStore,
+    did: &str,
+    stored: JsValue,
+    mark: &RevMark,
+) -> Result<JsValue, JsValue> {
+    if raises(mark_from_js(stored.clone())?.as_ref(), mark) {
+        store.put_with_key(&serde_wasm_bindgen::to_value(mark)?, &did.into())?;
+    }
+    Ok(stored)
+}
+
+/// Keeps marks in an IndexedDB database, so they survive page reloads.
+pub struct IndexedDbRevStore(IdbDatabase);
+
+impl IndexedDbRevStore {
+    pub async fn open(name: &str) -> Result<IndexedDbRevStore, JsValue> {
+        // `indexedDB` rather than `window.indexedDB`, so workers can use it too
+        let factory: IdbFactory = Reflect::get(&js_sys::global(), &"indexedDB".into())?
+            .dyn_into()
+            .map_err(|_| "indexedDB is not available")?;
+        let request = factory.open_with_u32(name, 1)?;
+
+        let upgrade = request.clone();
+        let onupgradeneeded = Closure::once_into_js(move || {
+            if let Ok(db) = upgrade.result() {
+                let _ = db
+                    .unchecked_into::<IdbDatabase>()
+                    .create_object_store(OBJECT_STORE);
+            }
+        });
+        request.set_onupgradeneeded(Some(onupgradeneeded.unchecked_ref()));
+
+        let db = request_done(&request).await?;
+        Ok(IndexedDbRevStore(db.unchecked_into()))
+    }
+}
+
+impl RevStore for IndexedDbRevStore {
+    fn get<'a>(&'a self, did: &'a str) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        async move {
+            let request = self
+                .0
+                .transaction_with_str(OBJECT_STORE)?
+                .object_store(OBJECT_STORE)?
+                .get(&did.into())?;
+            mark_from_js(request_done(&request).await?)
+        }
+        .boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        did: &'a str,
+        mark: RevMark,
+    ) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        async move {
+            // readwrite transactions on the same store run one at a time, even
+            // across tabs and workers, so the read and the write can't
+            // interleave with another verification's
+            let transaction = self
+                .0
+                .transaction_with_str_and_mode(OBJECT_STORE, IdbTransactionMode::Readwrite)?;
+            let done = transaction_done(&transaction);
+            let store = transaction.object_store(OBJECT_STORE)?;
+            let request = store.get(&did.into())?;
+            let did = did.to_owned();
+            let stored = request_then(&request, move |stored| {
+                put_if_raised(&store, &did, stored, &mark)
+            })
+            .await?;
+            done.await?;
+            mark_from_js(stored)
+        }
+        .boxed_local()
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_REV_STORE: &str = r#"
+export interface RevMark {
+  rev: string;
+  cid: string;
+}
+
+export interface RevStore {
+  get(did: string): RevMark | null | undefined | Promise<RevMark | null | undefined>;
+  /** Stores `mark` unless the stored rev is at least as new, atomically, and returns the mark stored before. */
+  put(did: string, mark: RevMark): RevMark | null | undefined | Promise<RevMark | null | undefined>;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// Any object with `get(did)` and `put(did, {rev, cid})` methods, which
+    /// may return promises, e.g. the `FileRevStore` of the node and deno
+    /// entry points. `put` must keep the stored rev when it is at least as
+    /// new, and return the mark stored before.
+    #[wasm_bindgen(typescript_type = "RevStore")]
+    pub type JsRevStore;
+
+    #[wasm_bindgen(method, catch, js_name = get)]
+    fn get_mark(this: &JsRevStore, did: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = put)]
+    fn put_mark(this: &JsRevStore, did: &str, mark: JsValue) -> Result<JsValue, JsValue>;
+}
+
+impl RevStore for JsRevStore {
+    fn get<'a>(&'a self, did: &'a str) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        async move {
+            let mark = JsFuture::from(Promise::resolve(&self.get_mark(did)?)).await?;
+            mark_from_js(mark)
+        }
+        .boxed_local()
+    }
+
+    fn put<'a>(
+        &'a self,
+        did: &'a str,
+        mark: RevMark,
+    ) -> LocalBoxFuture<'a, Result<Option<RevMark>, JsValue>> {
+        async move {
+            let mark = serde_wasm_bindgen::to_value(&mark)?;
+            let stored = JsFuture::from(Promise::resolve(&self.put_mark(did, mark)?)).await?;
+            mark_from_js(stored)
+        }
+        .boxed_local()
+    }
+}
+
+thread_local! {
+    static REV_STORE: RefCell<Option<Rc<dyn RevStore>>> = const { RefCell::new(None) };
+}
+
+pub fn set_rev_store(store: Option<Rc<dyn RevStore>>) {
+    REV_STORE.with(|cell| *cell.borrow_mut() = store);
+}
+
+/// Remember verified revs in memory for the lifetime of the module.
+#[wasm_bindgen]
+pub fn use_memory_rev_store() {
+    set_rev_store(Some(Rc::new(MemoryRevStore::default())));
+}
+
+/// Remember verified revs in the IndexedDB database `name`.
+#[wasm_bindgen]
+pub async fn use_indexed_db_rev_store(name: &str) -> Result<(), JsValue> {
+    set_rev_store(Some(Rc::new(IndexedDbRevStore::open(name).await?)));
+    Ok(())
+}
+
+/// Remember verified revs in a store implemented in JS.
+#[wasm_bindgen]
+pub fn use_rev_store(store: JsRevStore) {
+    set_rev_store(Some(Rc::new(store)));
+}
+
+#[wasm_bindgen]
+pub fn clear_rev_store() {
+    set_rev_store(None);
+}
+
+fn rolled_back(message: &str) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name("RepositoryRolledBack");
+    error.into()
+}
+
+/// Records a freshly verified commit of `did` unless a newer one was seen,
+/// failing with a `RepositoryRolledBack` error if the repo went backwards.
+/// Does nothing if no store is configured.
+pub async fn check_and_record(did: &Did, rev: &str, cid: &str) -> Result<(), JsValue> {
+    let store = match REV_STORE.with(|cell| cell.borrow().clone()) {
+        Some(store) => store,
+        None => return Ok(()),
+    };
+
+    let rev = Tid::from_str(rev).map_err(|_| "commit rev is not a valid tid")?;
+    let mark = RevMark {
+        rev: rev.to_string(),
+        cid: cid.to_owned(),
+    };
+    let stored = match store.put(did.as_str(), mark).await? {
+        Some(stored) => stored,
+        None => return Ok(()),
+    };
+    let seen = match Tid::from_str(&stored.rev) {
+        Ok(seen) => seen,
+        Err(_) => return Ok(()),
+    };
+
+    if rev < seen {
+        return Err(rolled_back(&format!(
+            "repository rolled back: commit rev {} is older than previously verified rev {}",
+            rev, seen
+        )));
+    }
+    if rev == seen && stored.cid != cid {
+        return Err(rolled_back(&format!(
+            "repository rolled back: two different commits with rev {}",
+            rev
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+
+    fn mark(rev: &str) -> RevMark {
+        RevMark {
+            rev: rev.into(),
+            cid: format!("cid-{}", rev),
+        }
+    }
+
+    /// Puts an older, a newer and an equal mark, and two racing ones, and
+    /// checks the stored rev only ever goes up.
+    async fn never_lowers_rev(store: &dyn RevStore) {
+        assert_eq!(store.put(DID, mark("3kvr3ymffwc2m")).await.unwrap(), None);
+        assert_eq!(
+            store.put(DID, mark("3kvr3ymffwc22")).await.unwrap(),
+            Some(mark("3kvr3ymffwc2m"))
+        );
+        assert_eq!(store.get(DID).await.unwrap(), Some(mark("3kvr3ymffwc2m")));
+
+        let (newer, older) = futures_util::join!(
+            store.put(DID, mark("3kvr3ymffwc2z")),
+            store.put(DID, mark("3kvr3ymffwc2p"))
+        );
+        assert_eq!(newer.unwrap(), Some(mark("3kvr3ymffwc2m")));
+        assert_eq!(older.unwrap(), Some(mark("3kvr3ymffwc2z")));
+        assert_eq!(store.get(DID).await.unwrap(), Some(mark("3kvr3ymffwc2z")));
+    }
+
+    #[wasm_bindgen_test]
+    async fn memory_store_never_lowers_rev() {
+        never_lowers_rev(&MemoryRevStore::default()).await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn indexed_db_store_never_lowers_rev() {
+        let name = format!("revs-{}", js_sys::Date::now());
+        let store = match IndexedDbRevStore::open(&name).await {
+            Ok(store) => store,
+            // only browsers have IndexedDB
+            Err(err) if err.as_string().as_deref() == Some("indexedDB is not available") => return,
+            Err(err) => panic!("{:?}", err),
+        };
+        never_lowers_rev(&store).await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn js_store_never_lowers_rev() {
+        let store: JsRevStore = js_sys::Function::new_no_args(
+            r#"
+            const marks = new Map();
+            return {
+              get: (did) => marks.get(did),
+              put: async (did, mark) => {
+                const stored = marks.get(did);
+                if (!stored || stored.rev < mark.rev) {
+                  marks.set(did, mark);
+                }
+                return stored;
+              },
+            };
+            "#,
+        )
+        .call0(&JsValue::NULL)
+        .unwrap()
+        .unchecked_into();
+        never_lowers_rev(&store).await;
+    }
+}
@@ -0,0 +1,456 @@
+use std::fmt;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+fn is_domain_label(label: &str) -> bool {
+    (1..=63).contains(&label.len())
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+}
+
+pub fn is_did(s: &str) -> bool {
+    let rest = match s.strip_prefix("did:") {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let (method, id) = match rest.split_once(':') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let bytes = id.as_bytes();
+    s.len() <= 2048
+        && !method.is_empty()
+        && method.bytes().all(|c| c.is_ascii_lowercase())
+        && !id.is_empty()
+        && !id.ends_with(':')
+        && bytes.iter().enumerate().all(|(i, c)| match c {
+            // percent escapes must be complete
+            b'%' => bytes.len() > i + 2 && bytes[i + 1..i + 3].iter().all(u8::is_ascii_hexdigit),
+            c => c.is_ascii_alphanumeric() || b"._:-".contains(c),
+        })
+}
+
+pub fn is_handle(s: &str) -> bool {
+    let labels: Vec<&str> = s.split('.').collect();
+    s.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| is_domain_label(label))
+        && !labels[labels.len() - 1].as_bytes()[0].is_ascii_digit()
+}
+
+pub fn is_nsid(s: &str) -> bool {
+    let segments: Vec<&str> = s.split('.').collect();
+    let (name, authority) = match segments.split_last() {
+        Some(parts) => parts,
+        None => return false,
+    };
+    s.len() <= 317
+        && segments.len() >= 3
+        && authority.iter().map(|label| label.len() + 1).sum::<usize>() <= 254
+        && authority.iter().all(|label| is_domain_label(label))
+        && !authority[0].as_bytes()[0].is_ascii_digit()
+        && (1..=63).contains(&name.len())
+        && name.as_bytes()[0].is_ascii_alphabetic()
+        && name.bytes().all(|c| c.is_ascii_alphanumeric())
+}
+
+pub fn is_record_key(s: &str) -> bool {
+    (1..=512).contains(&s.len())
+        && s != "."
+        && s != ".."
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"._:~-".contains(&c))
+}
+
+pub const TID_ALPHABET: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+pub fn is_tid(s: &str) -> bool {
+    s.len() == 13
+        && s.bytes().all(|c| TID_ALPHABET.contains(&c))
+        // the top bit is always zero
+        && TID_ALPHABET[..16].contains(&s.as_bytes()[0])
+}
+
+macro_rules! string_type {
+    ($name:ident, $check:ident, $error:expr) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = JsValue;
+
+            fn from_str(s: &str) -> Result<$name, JsValue> {
+                if $check(s) {
+                    Ok($name(s.to_owned()))
+                } else {
+                    Err($error.into())
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+string_type!(Did, is_did, "invalid did");
+string_type!(Handle, is_handle, "invalid handle");
+string_type!(Nsid, is_nsid, "invalid nsid");
+string_type!(RecordKey, is_record_key, "invalid record key");
+string_type!(Tid, is_tid, "invalid tid");
+
+impl Did {
+    pub fn method(&self) -> &str {
+        self.0[4..].split(':').next().unwrap()
+    }
+
+    /// Everything after the method.
+    pub fn identifier(&self) -> &str {
+        &self.0[5 + self.method().len()..]
+    }
+}
+
+impl Handle {
+    /// Handles are case-insensitive; this is the form to compare and store.
+    pub fn normalized(&self) -> String {
+        self.0.to_ascii_lowercase()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtIdentifier {
+    Did(Did),
+    Handle(Handle),
+}
+
+impl FromStr for AtIdentifier {
+    type Err = JsValue;
+
+    fn from_str(s: &str) -> Result<AtIdentifier, JsValue> {
+        if s.starts_with("did:") {
+            Did::from_str(s).map(AtIdentifier::Did)
+        } else {
+            Handle::from_str(s).map(AtIdentifier::Handle)
+        }
+    }
+}
+
+impl fmt::Display for AtIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtIdentifier::Did(did) => did.fmt(f),
+            AtIdentifier::Handle(handle) => handle.fmt(f),
+        }
+    }
+}
+
+/// An `at://` uri in the restricted syntax: an authority, optionally
+/// followed by a collection and a record key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtUri {
+    pub authority: AtIdentifier,
+    pub collection: Option<Nsid>,
+    pub rkey: Option<RecordKey>,
+}
+
+impl AtUri {
+    /// The parts of a uri naming a single record in a repo identified by
+    /// did.
+    pub fn record(&self) -> Result<(&Did, &Nsid, &RecordKey), JsValue> {
+        match (&self.authority, &self.collection, &self.rkey) {
+            (AtIdentifier::Did(did), Some(collection), Some(rkey)) => Ok((did, collection, rkey)),
+            (AtIdentifier::Handle(_), _, _) => Err("record uri must use a did".into()),
+            _ => Err("uri doesn't name a record".into()),
+        }
+    }
+}
+
+impl FromStr for AtUri {
+    type Err = JsValue;
+
+    fn from_str(s: &str) -> Result<AtUri, JsValue> {
+        let rest = match s.strip_prefix("at://") {
+            Some(rest) if s.len() <= 8192 => rest,
+            _ => return Err("invalid at uri".into()),
+        };
+        let mut parts = rest.split('/');
+        let authority = AtIdentifier::from_str(parts.next().unwrap())?;
+        let collection = parts.next().map(Nsid::from_str).transpose()?;
+        let rkey = parts.next().map(RecordKey::from_str).transpose()?;
+        if parts.next().is_some() {
+            return Err("invalid at uri".into());
+        }
+        Ok(AtUri {
+            authority,
+            collection,
+            rkey,
+        })
+    }
+}
+
+impl fmt::Display for AtUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at://{}", self.authority)?;
+        if let Some(ref collection) = self.collection {
+            write!(f, "/{}", collection)?;
+        }
+        if let Some(ref rkey) = self.rkey {
+            write!(f, "/{}", rkey)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // from bluesky-social/atproto-interop-tests, syntax/
+    const DID_VALID: &[&str] = &[
+        "did:method:val",
+        "did:method:VAL",
+        "did:method:val123",
+        "did:method:123",
+        "did:method:val-two",
+        "did:method:val_two",
+        "did:method:val.two",
+        "did:method:val:two",
+        "did:method:val%BB",
+        "did:m:v",
+        "did:method::::val",
+        "did:method:-",
+        "did:method:-:_:.",
+        "did:plc:7iza6de2dwap2sbkpav7c6c6",
+        "did:web:example.com",
+        "did:web:localhost%3A1234",
+        "did:onion:2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid",
+    ];
+
+    const DID_INVALID: &[&str] = &[
+        "did",
+        "didmethodval",
+        "method:did:val",
+        "did:method:",
+        "didmethod:val",
+        "did:methodval",
+        ":did:method:val",
+        "did.method.val",
+        "did:method:val:",
+        "did:method:val%",
+        "DID:method:val",
+        "did:METHOD:val",
+        "did:m123:val",
+        "did:method:val/two",
+        "did:method:val?two",
+        "did:method:val#two",
+        "did:method:val%B",
+    ];
+
+    const HANDLE_VALID: &[&str] = &[
+        "A.ISI.EDU",
+        "XX.LCS.MIT.EDU",
+        "john.test",
+        "jan.test",
+        "a234567890123456789.test",
+        "john2.test",
+        "john-john.test",
+        "john.bsky.app",
+        "jo.hn",
+        "a.co",
+        "a.org",
+        "joh.n",
+        "j0.h0",
+        "jaymome-johnber123456.test",
+        "jay.mome-johnber123456.test",
+        "xn--ls8h.test",
+        "example.t",
+        "laptop.local",
+        "blah.arpa",
+        "11.test",
+        "a.s-p-a-c-e.test",
+    ];
+
+    const HANDLE_INVALID: &[&str] = &[
+        "jo@hn.test",
+        "💩.test",
+        "john..test",
+        "xn--bcher-.tld",
+        "john.0",
+        "cn.8",
+        "www.masełkowski.pl.com",
+        "org",
+        "name.org.",
+        "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion.",
+        "-john.test",
+        "john-.test",
+        "john.-test",
+        "john.test-",
+        "john.test.",
+        "",
+        " john.test",
+    ];
+
+    const NSID_VALID: &[&str] = &[
+        "com.example.fooBar",
+        "net.users.bob.ping",
+        "a.b.c",
+        "m.xn--masekowski-d0b.pl",
+        "one.two.three",
+        "one.two.three.four-and.FiVe",
+        "one.2.three",
+        "a-0.b-1.c",
+        "a0.b1.cc",
+        "cn.8.lex.stuff",
+        "test.12345.record",
+        "a01.thing.record",
+        "a.0.c",
+        "xn--fiqs8s.xn--fiqa61au8b7zsevnm8ak20mc4a87e.record.two",
+        "com.example.f00",
+    ];
+
+    const NSID_INVALID: &[&str] = &[
+        "com.example.foo.*",
+        "com.example.foo.blah*",
+        "com.example.f00.*",
+        "com.exa💩ple.thing",
+        "a-0.b-1.c-3",
+        "a-0.b-1.c-o",
+        "1.0.0.127.record",
+        "0two.example.foo",
+        "example.com",
+        "com.example",
+        "a.",
+        ".one.two.three",
+        "one.two.three ",
+        "one.two..three",
+        "one .two.three",
+        " one.two.three",
+        "com.atproto.feed.p@st",
+        "com.atproto.feed.p_st",
+        "com.atproto.feed.p*st",
+        "com.atproto.feed.po#t",
+        "com.atproto.feed.p!ot",
+        "com.example-.foo",
+    ];
+
+    const RECORD_KEY_VALID: &[&str] = &[
+        "3jui7kd54zh2y",
+        "self",
+        "example.com",
+        "~1.2-3_",
+        "dHJ1ZQ",
+        "pre:fix",
+        "_",
+    ];
+
+    const RECORD_KEY_INVALID: &[&str] = &[
+        "alpha/beta",
+        ".",
+        "..",
+        "#extra",
+        "@handle",
+        "any space",
+        "any+space",
+        "number[3]",
+        "number(3)",
+        "\"quote\"",
+        "dHJ1ZQ==",
+        "",
+    ];
+
+    const TID_VALID: &[&str] = &[
+        "3jzfcijpj2z2a",
+        "7777777777777",
+        "3zzzzzzzzzzzz",
+        "2222222222222",
+    ];
+
+    const TID_INVALID: &[&str] = &[
+        "3jzfcijpj2z21",
+        "0000000000000",
+        "3jzfcijpj2z2aa",
+        "3jzfcijpj2z2",
+        "222",
+        "3jzf-cij-pj2z-2a",
+        "zzzzzzzzzzzzz",
+        "kjzfcijpj2z2a",
+    ];
+
+    const AT_URI_VALID: &[&str] = &[
+        "at://did:plc:asdf123",
+        "at://user.bsky.social",
+        "at://did:plc:asdf123/com.atproto.feed.post",
+        "at://did:plc:asdf123/com.atproto.feed.post/record",
+        "at://did:web:localhost%3A1234/com.atproto.feed.post/record",
+        "at://did:plc:ewvi7nxzyoun6zhxrhs64oiz/app.bsky.feed.post/3kvr3ymffwc2m",
+    ];
+
+    const AT_URI_INVALID: &[&str] = &[
+        "a://did:plc:asdf123",
+        "at//did:plc:asdf123",
+        "at:/a/did:plc:asdf123",
+        "at:/did:plc:asdf123",
+        "AT://did:plc:asdf123",
+        "http://did:plc:asdf123",
+        "://did:plc:asdf123",
+        "at:did:plc:asdf123",
+        "at:/did:plc:asdf123",
+        "at:///did:plc:asdf123",
+        "at://:/did:plc:asdf123",
+        "at:/ /did:plc:asdf123",
+        "at://did:plc:asdf123 ",
+        "at://did:plc:asdf123/ ",
+        " at://did:plc:asdf123",
+        "at://did:plc:asdf123/com.atproto.feed.post ",
+        "at://did:plc:asdf123/com.atproto.feed.post# ",
+        "at://did:plc:asdf123/com.atproto.feed.post/",
+        "at://did:plc:asdf123/com.atproto.feed.post/record/",
+        "at://did:plc:asdf123/com.atproto.feed.post/record/extra",
+        "at://did:plc:asdf123/short/stuff",
+        "at://did:plc:asdf123/12345",
+        "at://user.bsky.social/",
+        "at://",
+    ];
+
+    fn check<T: FromStr>(valid: &[&str], invalid: &[&str]) {
+        for s in valid {
+            assert!(T::from_str(s).is_ok(), "{:?} should be valid", s);
+        }
+        for s in invalid {
+            assert!(T::from_str(s).is_err(), "{:?} should be invalid", s);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn interop_vectors() {
+        check::<Did>(DID_VALID, DID_INVALID);
+        check::<Handle>(HANDLE_VALID, HANDLE_INVALID);
+        check::<Nsid>(NSID_VALID, NSID_INVALID);
+        check::<RecordKey>(RECORD_KEY_VALID, RECORD_KEY_INVALID);
+        check::<Tid>(TID_VALID, TID_INVALID);
+        check::<AtUri>(AT_URI_VALID, AT_URI_INVALID);
+    }
+
+    #[wasm_bindgen_test]
+    fn round_trip() {
+        for s in AT_URI_VALID {
+            assert_eq!(AtUri::from_str(s).unwrap().to_string(), *s);
+        }
+        let did = Did::from_str("did:web:localhost%3A1234").unwrap();
+        assert_eq!(did.method(), "web");
+        assert_eq!(did.identifier(), "localhost%3A1234");
+    }
+}
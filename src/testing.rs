@@ -0,0 +1,1481 @@
+//! An in-memory stand-in for PLC and PDS hosts, and accounts that publish
+//! signed repos to it.
+
+use crate::repo::RepoWriter;
+use crate::syntax::Tid;
+use crate::transport::{set_transport, Transport};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use sha2::Digest;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{Array, Date, Function, Object, Promise, Reflect, Uint8Array, JSON};
+use web_sys::{AbortSignal, Headers, ReadableStream, Response, ResponseInit};
+
+struct Route {
+    status: u16,
+    content_type: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Answers requests from a table of canned responses, and 404s everything
+/// else.
+#[derive(Default)]
+pub struct MockNetwork {
+    routes: RefCell<HashMap<String, Route>>,
+    failures: RefCell<HashMap<String, (u16, u32)>>,
+    hanging: RefCell<HashSet<String>>,
+    stalling: RefCell<HashSet<String>>,
+    chunked: RefCell<HashMap<String, usize>>,
+    requests: RefCell<Vec<String>>,
+    cancelled: Rc<RefCell<Vec<String>>>,
+}
+
+impl MockNetwork {
+    /// Creates a network and routes all of the verifier's requests to it.
+    pub fn install() -> Rc<MockNetwork> {
+        let network = Rc::new(MockNetwork::default());
+        set_transport(network.clone());
+        network
+    }
+
+    pub fn serve(&self, url: &str, status: u16, content_type: &str, body: Vec<u8>) {
+        let route = Route {
+            status,
+            content_type: content_type.to_owned(),
+            headers: Vec::new(),
+            body,
+        };
+        self.routes.borrow_mut().insert(url.to_owned(), route);
+    }
+
+    /// Adds a header to the response already served for `url`.
+    pub fn set_header(&self, url: &str, name: &str, value: &str) {
+        if let Some(route) = self.routes.borrow_mut().get_mut(url) {
+            route.headers.push((name.to_owned(), value.to_owned()));
+        }
+    }
+
+    /// Answers the next `times` requests for `url` with an empty `status`.
+    pub fn fail(&self, url: &str, status: u16, times: u32) {
+        self.failures
+            .borrow_mut()
+            .insert(url.to_owned(), (status, times));
+    }
+
+    /// Never answers requests for `url`, until they are aborted.
+    pub fn hang(&self, url: &str) {
+        self.hanging.borrow_mut().insert(url.to_owned());
+    }
+
+    /// Sends the headers for `url` but never any of the body.
+    pub fn stall_body(&self, url: &str) {
+        self.stalling.borrow_mut().insert(url.to_owned());
+    }
+
+    /// Streams the body for `url` in chunks of `size` bytes, without a
+    /// `Content-Length`.
+    pub fn chunk(&self, url: &str, size: usize) {
+        self.chunked.borrow_mut().insert(url.to_owned(), size);
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.borrow().clone()
+    }
+
+    /// The requests whose signal was aborted, in order.
+    pub fn cancelled(&self) -> Vec<String> {
+        self.cancelled.borrow().clone()
+    }
+
+    fn respond(&self, url: &str) -> Result<Response, JsValue> {
+        self.requests.borrow_mut().push(url.to_owned());
+
+        let routes = self.routes.borrow();
+        let not_found = Route {
+            status: 404,
+            content_type: "text/plain".into(),
+            headers: Vec::new(),
+            body: b"not found".to_vec(),
+        };
+        let mut route = routes.get(url).unwrap_or(&not_found);
+        let failure;
+        if let Some((status, times)) = self.failures.borrow_mut().get_mut(url) {
+            if *times > 0 {
+                *times -= 1;
+                failure = Route {
+                    status: *status,
+                    content_type: "text/plain".into(),
+                    headers: Vec::new(),
+                    body: Vec::new(),
+                };
+                route = &failure;
+            }
+        }
+
+        let headers = Headers::new()?;
+        headers.set("content-type", &route.content_type)?;
+        let chunk_size = self.chunked.borrow().get(url).copied();
+        if chunk_size.is_none() {
+            headers.set("content-length", &route.body.len().to_string())?;
+        }
+        for (name, value) in route.headers.iter() {
+            headers.set(name, value)?;
+        }
+        let init = ResponseInit::new();
+        init.set_status(route.status);
+        init.set_headers(&headers);
+
+        if self.stalling.borrow().contains(url) {
+            let body = ReadableStream::new()?;
+            return Response::new_with_opt_readable_stream_and_init(Some(&body), &init);
+        }
+        if let Some(size) = chunk_size {
+            let body = chunked_body(&route.body, size)?;
+            return Response::new_with_opt_readable_stream_and_init(Some(&body), &init);
+        }
+        let body = Uint8Array::from(route.body.as_slice());
+        Response::new_with_opt_buffer_source_and_init(Some(&body), &init)
+    }
+}
+
+/// A stream that yields `body` in chunks of `size` bytes.
+fn chunked_body(body: &[u8], size: usize) -> Result<ReadableStream, JsValue> {
+    let chunks: Vec<Uint8Array> = body.chunks(size).map(Uint8Array::from).collect();
+    let start = Closure::once_into_js(move |controller: JsValue| {
+        let call = |method: &str, args: &Array| {
+            let method: Function = Reflect::get(&controller, &method.into())
+                .unwrap()
+                .unchecked_into();
+            method.apply(&controller, args).unwrap();
+        };
+        for chunk in chunks {
+            call("enqueue", &Array::of1(&chunk));
+        }
+        call("close", &Array::new());
+    });
+    let source = Object::new();
+    Reflect::set(&source, &"start".into(), &start)?;
+    ReadableStream::new_with_underlying_source(&source)
+}
+
+impl Transport for MockNetwork {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        signal: &'a AbortSignal,
+    ) -> LocalBoxFuture<'a, Result<Response, JsValue>> {
+        let cancelled = self.cancelled.clone();
+        let url_owned = url.to_owned();
+        let on_abort = Closure::once_into_js(move || cancelled.borrow_mut().push(url_owned));
+        let _ = signal.add_event_listener_with_callback("abort", on_abort.unchecked_ref());
+
+        if self.hanging.borrow().contains(url) {
+            self.requests.borrow_mut().push(url.to_owned());
+            let aborted = Promise::new(&mut |resolve: Function, _| {
+                let _ = signal.add_event_listener_with_callback("abort", &resolve);
+            });
+            return async move {
+                JsFuture::from(aborted).await?;
+                Err("aborted".into())
+            }
+            .boxed_local();
+        }
+        let response = self.respond(url);
+        async move { response }.boxed_local()
+    }
+}
+
+pub const PDS: &str = "https://pds.test";
+
+pub fn plc_url(did: &str) -> String {
+    format!("https://plc.directory/{}", did)
+}
+
+pub fn get_record_url(did: &str, collection: &str, rkey: &str) -> String {
+    format!(
+        "{}/xrpc/com.atproto.sync.getRecord?did={}&collection={}&rkey={}",
+        PDS, did, collection, rkey
+    )
+}
+
+pub fn get_blob_url(did: &str, cid: &str) -> String {
+    format!(
+        "{}/xrpc/com.atproto.sync.getBlob?did={}&cid={}",
+        PDS, did, cid
+    )
+}
+
+/// A TID `offset_secs` away from now.
+pub fn rev_at(offset_secs: i64) -> String {
+    let micros = Date::now() as i64 * 1000 + offset_secs * 1_000_000;
+    Tid::new(micros as u64, 0).to_string()
+}
+
+pub fn json(text: &str) -> JsValue {
+    JSON::parse(text).unwrap()
+}
+
+pub fn post(text: &str) -> JsValue {
+    json(&format!(
+        r#"{{"$type":"app.bsky.feed.post","text":"{}","createdAt":"2024-08-01T12:00:00.000Z"}}"#,
+        text
+    ))
+}
+
+/// The message of a string error, or the `name` of a JS `Error`.
+pub fn error_text(err: JsValue) -> String {
+    match err.as_string() {
+        Some(message) => message,
+        None => Reflect::get(&err, &"name".into())
+            .ok()
+            .and_then(|name| name.as_string())
+            .unwrap_or_else(|| format!("{:?}", err)),
+    }
+}
+
+/// A `did:plc` account with a deterministic key and a repo on the mock PDS.
+pub struct TestAccount {
+    pub did: String,
+    pub repo: RepoWriter,
+    records: Vec<(String, String)>,
+}
+
+impl TestAccount {
+    pub fn new(name: &str, curve: &str) -> TestAccount {
+        let did = format!("did:plc:{}", name);
+        let secret = sha2::Sha256::digest(name.as_bytes());
+        TestAccount {
+            repo: RepoWriter::new(&did, curve, &secret).unwrap(),
+            did,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn uri(&self, collection: &str, rkey: &str) -> String {
+        format!("at://{}/{}/{}", self.did, collection, rkey)
+    }
+
+    pub fn did_doc_with_key(&self, public_key_multibase: &str) -> JsValue {
+        json(&format!(
+            r##"{{
+                "id": "{did}",
+                "alsoKnownAs": ["at://{did}.test"],
+                "verificationMethod": [{{
+                    "id": "{did}#atproto",
+                    "type": "Multikey",
+                    "controller": "{did}",
+                    "publicKeyMultibase": "{key}"
+                }}],
+                "service": [{{
+                    "id": "#atproto_pds",
+                    "type": "AtprotoPersonalDataServer",
+                    "serviceEndpoint": "{pds}"
+                }}]
+            }}"##,
+            did = self.did,
+            key = public_key_multibase,
+            pds = PDS
+        ))
+    }
+
+    pub fn did_doc(&self) -> JsValue {
+        self.did_doc_with_key(&self.repo.public_key_multibase())
+    }
+
+    /// Puts `record` and returns its cid.
+    pub fn put(&mut self, collection: &str, rkey: &str, record: JsValue) -> String {
+        self.records.push((collection.to_owned(), rkey.to_owned()));
+        self.repo.put_record(collection, rkey, record).unwrap()
+    }
+
+    pub fn commit(&mut self, rev: &str) -> String {
+        self.repo.commit(rev).unwrap()
+    }
+
+    /// Serves the did document from PLC and a `getRecord` proof for every
+    /// record put so far from the PDS, as of the last commit.
+    pub fn publish(&self, network: &MockNetwork) {
+        let did_doc = JSON::stringify(&self.did_doc())
+            .unwrap()
+            .as_string()
+            .unwrap();
+        network.serve(
+            &plc_url(&self.did),
+            200,
+            "application/json",
+            did_doc.into_bytes(),
+        );
+        for (collection, rkey) in self.records.iter() {
+            network.serve(
+                &get_record_url(&self.did, collection, rkey),
+                200,
+                "application/vnd.ipld.car",
+                self.repo.get_record_proof(collection, rkey).unwrap(),
+            );
+        }
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::blob::{verify_blob, verify_record_blobs};
+    use crate::indexed_car::verify_record_in_archive;
+    use crate::mst::{self, MstError};
+    use crate::repo::{sign_commit, SigningKey};
+    use crate::rev_store::{clear_rev_store, use_memory_rev_store};
+    use crate::transport::sleep;
+    use crate::value::{self, Value};
+    use crate::{
+        authenticate_post, authenticate_post_with_doc, fetch_verified_record, records, thread,
+        verify_record, verify_record_with_doc,
+    };
+    use crate::{car, cid_for_cbor, hasher, record_to_cbor, UnsignedCommitObject};
+    use cid::Cid;
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys::AbortController;
+
+    const POSTS: &str = "app.bsky.feed.post";
+
+    fn multibase(bytes: &[u8]) -> String {
+        libipld::multibase::encode(libipld::multibase::Base::Base58Btc, bytes)
+    }
+
+    fn field(value: &JsValue, key: &str) -> JsValue {
+        Reflect::get(value, &key.into()).unwrap()
+    }
+
+    fn published(name: &str, curve: &str) -> (Rc<MockNetwork>, TestAccount, String) {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new(name, curve);
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.put(POSTS, "3kvr3ymffwc2n", post("world"));
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+        (network, account, cid)
+    }
+
+    #[wasm_bindgen_test]
+    async fn authenticates_record() {
+        let (network, account, cid) = published("alice", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let report = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        assert_eq!(field(&report, "did").as_string().unwrap(), account.did);
+        assert_eq!(field(&report, "curve").as_string().unwrap(), "k256");
+        assert_eq!(field(&report, "pds").as_string().unwrap(), PDS);
+        assert_eq!(
+            field(&report, "didDocSource").as_string().unwrap(),
+            plc_url(&account.did)
+        );
+        assert!(field(&report, "record").is_undefined());
+        assert_eq!(
+            network.requests(),
+            [
+                plc_url(&account.did),
+                get_record_url(&account.did, POSTS, "3kvr3ymffwc2m")
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn authenticates_p256_record_with_doc() {
+        let (_network, account, cid) = published("bob", "p256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let options = json(r#"{"returnRecord": true}"#);
+        let report =
+            authenticate_post_with_doc(&uri, &cid, post("hello"), account.did_doc(), options)
+                .await
+                .unwrap();
+        assert_eq!(field(&report, "curve").as_string().unwrap(), "p256");
+        assert_eq!(
+            field(&report, "didDocSource").as_string().unwrap(),
+            "provided"
+        );
+        let record = field(&report, "record");
+        assert_eq!(field(&record, "text").as_string().unwrap(), "hello");
+    }
+
+    #[wasm_bindgen_test]
+    async fn fetches_verified_record() {
+        let (_network, account, cid) = published("carol", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let verified = fetch_verified_record(&uri, JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        assert_eq!(field(&verified, "cid").as_string().unwrap(), cid);
+        let record = field(&verified, "record");
+        assert_eq!(field(&record, "text").as_string().unwrap(), "hello");
+    }
+
+    #[wasm_bindgen_test]
+    async fn accepts_canonical_blocks_in_strict_mode() {
+        let (_network, account, cid) = published("carol", "p256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let options = json(r#"{"strictCbor": true}"#);
+
+        let verified = fetch_verified_record(&uri, options.clone()).await.unwrap();
+        assert_eq!(field(&verified, "cid").as_string().unwrap(), cid);
+        authenticate_post(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn verifies_other_collections() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("erin", "k256");
+        let like = json(&format!(
+            r#"{{"$type":"{}","subject":{{"uri":"at://did:plc:alice/{}/3kvr3ymffwc2m","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}},"createdAt":"2024-08-01T12:00:00.000Z"}}"#,
+            records::LIKE,
+            POSTS
+        ));
+        let like_cid = account.put(records::LIKE, "3kvr3ymffwc2p", like.clone());
+        let profile = json(r#"{"$type":"app.bsky.actor.profile","displayName":"Erin"}"#);
+        let profile_cid = account.put(records::PROFILE, "self", profile.clone());
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+
+        let uri = account.uri(records::LIKE, "3kvr3ymffwc2p");
+        records::verify_like(&uri, &like_cid, like.clone(), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        records::verify_profile(&account.did, &profile_cid, profile, JsValue::UNDEFINED)
+            .await
+            .unwrap();
+
+        let err = records::verify_post(&uri, &like_cid, like, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "expected a app.bsky.feed.post record, got app.bsky.feed.like"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_record_type_mismatch() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("frank", "k256");
+        let cid = account.put(records::LIKE, "3kvr3ymffwc2p", post("hello"));
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+
+        let uri = account.uri(records::LIKE, "3kvr3ymffwc2p");
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "record $type app.bsky.feed.post doesn't match collection app.bsky.feed.like"
+        );
+        let err = fetch_verified_record(&uri, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "record $type app.bsky.feed.post doesn't match collection app.bsky.feed.like"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn verifies_thread() {
+        let (network, alice, root_cid) = published("alice", "k256");
+        let root_uri = alice.uri(POSTS, "3kvr3ymffwc2m");
+        let missing_uri = alice.uri(POSTS, "3kvr3ymffwc2z");
+
+        let mut bob = TestAccount::new("bob", "p256");
+        let reply = json(&format!(
+            r#"{{"$type":"app.bsky.feed.post","text":"hi","createdAt":"2024-08-01T12:00:00.000Z","reply":{{"root":{{"uri":"{root}","cid":"{cid}"}},"parent":{{"uri":"{root}","cid":"{cid}"}}}},"embed":{{"$type":"app.bsky.embed.record","record":{{"uri":"{missing}","cid":"{cid}"}}}}}}"#,
+            root = root_uri,
+            missing = missing_uri,
+            cid = root_cid
+        ));
+        bob.put(POSTS, "3kvr3ymffwc2p", reply);
+        bob.commit(&rev_at(-30));
+        bob.publish(&network);
+        let reply_uri = bob.uri(POSTS, "3kvr3ymffwc2p");
+
+        let graph = thread::verify_thread(&reply_uri, JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        let nodes: Array = field(&graph, "nodes").unchecked_into();
+        let statuses: Vec<(String, String)> = nodes
+            .iter()
+            .map(|node| {
+                let uri = field(&node, "uri").as_string().unwrap();
+                (uri, field(&node, "status").as_string().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (reply_uri.clone(), "verified".to_owned()),
+                (missing_uri, "failed".to_owned()),
+                (root_uri, "verified".to_owned()),
+            ]
+        );
+
+        let edges: Array = field(&graph, "edges").unchecked_into();
+        let edges: Vec<(String, JsValue)> = edges
+            .iter()
+            .map(|edge| {
+                let path = field(&edge, "path").as_string().unwrap();
+                (path, field(&edge, "cidMatches"))
+            })
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                ("embed.record".to_owned(), JsValue::NULL),
+                ("reply.parent".to_owned(), JsValue::TRUE),
+                ("reply.root".to_owned(), JsValue::TRUE),
+            ]
+        );
+
+        let options = json(r#"{"maxDepth": 0}"#);
+        let graph = thread::verify_thread(&reply_uri, options).await.unwrap();
+        let nodes: Array = field(&graph, "nodes").unchecked_into();
+        let status = field(&nodes.get(2), "status").as_string().unwrap();
+        assert_eq!(status, "skipped");
+    }
+
+    #[wasm_bindgen_test]
+    async fn reports_account_status() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("gina", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        let rev = rev_at(-60);
+        let commit_cid = account.commit(&rev);
+        account.publish(&network);
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let options = json(r#"{"checkAccountStatus": true}"#);
+
+        let status_url = format!(
+            "{}/xrpc/com.atproto.sync.getRepoStatus?did={}",
+            PDS, account.did
+        );
+        let latest_url = format!(
+            "{}/xrpc/com.atproto.sync.getLatestCommit?did={}",
+            PDS, account.did
+        );
+        let serve_json = |url: &str, body: String| {
+            network.serve(url, 200, "application/json", body.into_bytes())
+        };
+
+        serve_json(
+            &status_url,
+            format!(r#"{{"did":"{}","active":true}}"#, account.did),
+        );
+        serve_json(
+            &latest_url,
+            format!(r#"{{"cid":"{}","rev":"{}"}}"#, commit_cid, rev),
+        );
+        let report = verify_record(&uri, &cid, post("hello"), options.clone())
+            .await
+            .unwrap();
+        assert_eq!(field(&report, "active"), JsValue::TRUE);
+        assert_eq!(field(&report, "isLatestCommit"), JsValue::TRUE);
+        let latest = field(&report, "latestCommit");
+        assert_eq!(field(&latest, "cid").as_string().unwrap(), commit_cid);
+
+        let newer = rev_at(-10);
+        serve_json(
+            &latest_url,
+            format!(r#"{{"cid":"{}","rev":"{}"}}"#, cid, newer),
+        );
+        let report = verify_record(&uri, &cid, post("hello"), options.clone())
+            .await
+            .unwrap();
+        assert_eq!(field(&report, "isLatestCommit"), JsValue::FALSE);
+
+        serve_json(
+            &latest_url,
+            format!(r#"{{"cid":"{}","rev":"{}"}}"#, cid, rev_at(-120)),
+        );
+        let err = verify_record(&uri, &cid, post("hello"), options.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "getRecord returned a commit newer than getLatestCommit"
+        );
+
+        serve_json(
+            &status_url,
+            format!(
+                r#"{{"did":"{}","active":false,"status":"takendown"}}"#,
+                account.did
+            ),
+        );
+        let report = verify_record(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap();
+        assert_eq!(field(&report, "active"), JsValue::FALSE);
+        assert_eq!(field(&report, "status").as_string().unwrap(), "takendown");
+        assert!(field(&report, "latestCommit").is_undefined());
+    }
+
+    #[wasm_bindgen_test]
+    async fn retries_transient_errors() {
+        let (network, account, cid) = published("hana", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let plc = plc_url(&account.did);
+
+        network.fail(&plc, 503, 1);
+        verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        let plc_requests = network.requests().iter().filter(|url| **url == plc).count();
+        assert_eq!(plc_requests, 2);
+
+        network.fail(&plc, 503, 1);
+        let options = json(r#"{"retries": 0}"#);
+        let err = verify_record(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), format!("{} returned HTTP 503", plc));
+
+        let missing = account.uri(POSTS, "3kvr3ymffwc2z");
+        let err = verify_record(&missing, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2z");
+        assert_eq!(error_text(err), format!("{} returned HTTP 404", url));
+    }
+
+    #[wasm_bindgen_test]
+    async fn times_out_and_cancels_requests() {
+        let (network, account, cid) = published("ivan", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2m");
+        network.hang(&url);
+
+        let options = json(r#"{"timeoutMs": 20, "retries": 1}"#);
+        let err = verify_record(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            format!("request to {} timed out after 20ms", url)
+        );
+        assert_eq!(network.requests().iter().filter(|u| **u == url).count(), 2);
+
+        let controller = AbortController::new().unwrap();
+        let options = json("{}");
+        Reflect::set(&options, &"signal".into(), &controller.signal()).unwrap();
+        let abort_soon = async {
+            sleep(20).await.unwrap();
+            controller.abort();
+        };
+        let (result, _) = futures_util::join!(
+            verify_record(&uri, &cid, post("hello"), options.clone()),
+            abort_soon
+        );
+        assert_eq!(error_text(result.unwrap_err()), "AbortError");
+
+        let err = verify_record(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "AbortError");
+    }
+
+    #[wasm_bindgen_test]
+    async fn times_out_and_cancels_stalled_bodies() {
+        let (network, account, cid) = published("iris", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2m");
+        network.stall_body(&url);
+
+        let options = json(r#"{"timeoutMs": 50, "retries": 0}"#);
+        let err = verify_record(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            format!("request to {} timed out after 50ms", url)
+        );
+        assert_eq!(network.cancelled(), vec![url.clone()]);
+
+        let controller = AbortController::new().unwrap();
+        let options = json("{}");
+        Reflect::set(&options, &"signal".into(), &controller.signal()).unwrap();
+        let abort_soon = async {
+            sleep(20).await.unwrap();
+            controller.abort();
+        };
+        let (result, _) = futures_util::join!(
+            verify_record(&uri, &cid, post("hello"), options),
+            abort_soon
+        );
+        assert_eq!(error_text(result.unwrap_err()), "AbortError");
+        assert_eq!(network.cancelled(), vec![url.clone(), url]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn surfaces_xrpc_errors() {
+        let (network, account, cid) = published("jade", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2m");
+        let serve_error = |status: u16, body: &str| {
+            network.serve(&url, status, "application/json", body.as_bytes().to_vec())
+        };
+
+        serve_error(
+            400,
+            r#"{"error":"RecordNotFound","message":"Could not locate record"}"#,
+        );
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            field(&err, "message").as_string().unwrap(),
+            "Could not locate record"
+        );
+        assert_eq!(field(&err, "status"), JsValue::from(400));
+        assert_eq!(error_text(err), "RecordNotFound");
+
+        serve_error(
+            400,
+            r#"{"error":"RepoTakendown","message":"Repo has been takendown"}"#,
+        );
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "RepoTakendown");
+
+        serve_error(429, r#"{"message":"slow down"}"#);
+        network.set_header(&url, "retry-after", "120");
+        let before = network.requests().len();
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(field(&err, "retryAfter"), JsValue::from(120));
+        assert_eq!(error_text(err), "RateLimited");
+        // too long a wait to retry within the timeout
+        assert_eq!(network.requests().len() - before, 2);
+
+        serve_error(
+            429,
+            r#"{"error":"RateLimitExceeded","message":"Rate Limit Exceeded"}"#,
+        );
+        network.set_header(&url, "retry-after", "120");
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(field(&err, "error"), JsValue::from("RateLimitExceeded"));
+        assert_eq!(field(&err, "status"), JsValue::from(429));
+        assert_eq!(error_text(err), "RateLimited");
+
+        serve_error(400, "<html>bad gateway</html>");
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), format!("{} returned HTTP 400", url));
+
+        network.serve(&url, 200, "text/html", b"<html></html>".to_vec());
+        let err = verify_record(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            format!("{} returned text/html instead of a car file", url)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_tampered_record() {
+        let (_network, account, cid) = published("dave", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err = authenticate_post(&uri, &cid, post("goodbye"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "given cid doesn't match given record");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_record_missing_from_repo() {
+        let (_network, account, _) = published("erin", "k256");
+        let mut other = TestAccount::new("erin", "k256");
+        let cid = other.put(POSTS, "3kvr3ymffwc2m", post("not in the repo"));
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err = authenticate_post(&uri, &cid, post("not in the repo"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "could not find cid in signed roots");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_record_under_another_key() {
+        let (_network, account, cid) = published("eve", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2n");
+
+        let err = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "could not find cid in signed roots");
+    }
+
+    #[wasm_bindgen_test]
+    async fn extracts_proofs_from_repo_exports() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("pia", "k256");
+        let mut cids = HashMap::new();
+        for c in "234567abcdefghijklmnopqrstuvwxyz".chars() {
+            let rkey = format!("3kvr3ymffwc2{}", c);
+            cids.insert(rkey.clone(), account.put(POSTS, &rkey, post(&rkey)));
+        }
+        let commit = account.commit(&rev_at(-60));
+        let export = account.repo.export_repo().unwrap();
+
+        let rkey = "3kvr3ymffwc2m";
+        let proof = car::extract_record_proof(export.clone(), POSTS, rkey)
+            .await
+            .unwrap();
+        assert_eq!(proof, account.repo.get_record_proof(POSTS, rkey).unwrap());
+        network.serve(
+            &get_record_url(&account.did, POSTS, rkey),
+            200,
+            "application/vnd.ipld.car",
+            proof.clone(),
+        );
+        let report = verify_record_with_doc(
+            &account.uri(POSTS, rkey),
+            &cids[rkey],
+            post(rkey),
+            account.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap();
+        assert!(field(&report, "recordDepth").as_f64().unwrap() > 0.0);
+
+        // the commit, the nodes down to the record and the record, nothing else
+        let (_, everything) = car::read_car(&export).await.unwrap();
+        let (roots, blocks) = car::read_car(&proof).await.unwrap();
+        let commit = Cid::from_str(&commit).unwrap();
+        assert_eq!(roots, [commit]);
+        let data = Cid::from_str(&field(&report, "data").as_string().unwrap()).unwrap();
+        let (path, _) = mst::find_path(&everything, data, &format!("{}/{}", POSTS, rkey)).unwrap();
+        let mut expected: HashSet<Vec<u8>> = path.iter().map(|cid| cid.to_bytes()).collect();
+        expected.insert(commit.to_bytes());
+        expected.insert(Cid::from_str(&cids[rkey]).unwrap().to_bytes());
+        assert_eq!(blocks.keys().cloned().collect::<HashSet<_>>(), expected);
+        assert!(blocks.len() < everything.len());
+    }
+
+    #[wasm_bindgen_test]
+    async fn drops_deleted_records_from_exports() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("tess", "p256");
+        let kept = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        let deleted = account.put(POSTS, "3kvr3ymffwc2n", post("world"));
+        account.commit(&rev_at(-120));
+        assert!(account.repo.delete_record(POSTS, "3kvr3ymffwc2n").unwrap());
+        assert!(!account.repo.delete_record(POSTS, "3kvr3ymffwc2n").unwrap());
+        account.commit(&rev_at(-60));
+        let export = account.repo.export_repo().unwrap();
+
+        let err = car::extract_record_proof(export.clone(), POSTS, "3kvr3ymffwc2n")
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "record not found in repo");
+        let (_, blocks) = car::read_car(&export).await.unwrap();
+        assert!(!blocks.contains_key(&Cid::from_str(&deleted).unwrap().to_bytes()));
+
+        // what is left is still a properly signed tree
+        let proof = car::extract_record_proof(export, POSTS, "3kvr3ymffwc2m")
+            .await
+            .unwrap();
+        network.serve(
+            &get_record_url(&account.did, POSTS, "3kvr3ymffwc2m"),
+            200,
+            "application/vnd.ipld.car",
+            proof,
+        );
+        verify_record_with_doc(
+            &account.uri(POSTS, "3kvr3ymffwc2m"),
+            &kept,
+            post("hello"),
+            account.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_wrong_signing_key() {
+        let (_network, account, cid) = published("frank", "k256");
+        let impostor = TestAccount::new("mallory", "k256");
+        let did_doc = account.did_doc_with_key(&impostor.repo.public_key_multibase());
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err =
+            authenticate_post_with_doc(&uri, &cid, post("hello"), did_doc, JsValue::UNDEFINED)
+                .await
+                .unwrap_err();
+        assert_eq!(error_text(err), "signature not verified");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_mismatched_did() {
+        let (_network, account, cid) = published("grace", "k256");
+        let other = TestAccount::new("heidi", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err = authenticate_post_with_doc(
+            &uri,
+            &cid,
+            post("hello"),
+            other.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_text(err), "record uri did doesn't match did doc id");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_future_and_stale_revs() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("ivan", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.commit(&rev_at(3600));
+        account.publish(&network);
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "commit rev is in the future");
+
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+        let options = json(&format!(r#"{{"lastSeenRev": "{}"}}"#, rev_at(0)));
+        let err = authenticate_post(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "commit rev is older than the last seen rev"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn detects_rollback() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("judy", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.commit(&rev_at(-120));
+        let old_proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        use_memory_rev_store();
+        authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+
+        network.serve(
+            &get_record_url(&account.did, POSTS, "3kvr3ymffwc2m"),
+            200,
+            "application/vnd.ipld.car",
+            old_proof,
+        );
+        let err = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        clear_rev_store();
+        assert_eq!(error_text(err), "RepositoryRolledBack");
+    }
+
+    #[wasm_bindgen_test]
+    async fn failed_verification_keeps_stored_rev() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("ken", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.commit(&rev_at(-120));
+        let old_proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let mut other = TestAccount::new("ken", "k256");
+        let missing_cid = other.put(POSTS, "3kvr3ymffwc2m", post("not in the repo"));
+
+        use_memory_rev_store();
+        authenticate_post(
+            &uri,
+            &missing_cid,
+            post("not in the repo"),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap_err();
+
+        network.serve(
+            &get_record_url(&account.did, POSTS, "3kvr3ymffwc2m"),
+            200,
+            "application/vnd.ipld.car",
+            old_proof,
+        );
+        let result = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED).await;
+        clear_rev_store();
+        result.unwrap();
+    }
+
+    fn archive(car: Vec<u8>) -> web_sys::Blob {
+        let parts = Array::of1(&Uint8Array::from(&car[..]));
+        web_sys::Blob::new_with_u8_array_sequence(&parts).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn verifies_record_in_archive() {
+        let mut account = TestAccount::new("ivan", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.commit(&rev_at(-120));
+        let old_proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        let rev = rev_at(-60);
+        account.commit(&rev);
+        let proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        use_memory_rev_store();
+        let options = json(r#"{"returnRecord": true}"#);
+        let report =
+            verify_record_in_archive(archive(proof), &uri, &cid, account.did_doc(), options)
+                .await
+                .unwrap();
+        assert_eq!(field(&report, "rev").as_string().unwrap(), rev);
+        assert_eq!(field(&report, "recordDepth").as_f64().unwrap(), 0.0);
+        assert!(field(&report, "pds").is_undefined());
+        let record = field(&report, "record");
+        assert_eq!(field(&record, "text").as_string().unwrap(), "hello");
+
+        let err = verify_record_in_archive(
+            archive(old_proof),
+            &uri,
+            &cid,
+            account.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap_err();
+        clear_rev_store();
+        assert_eq!(error_text(err), "RepositoryRolledBack");
+    }
+
+    #[wasm_bindgen_test]
+    async fn checks_archived_record_type_and_cid() {
+        let mut account = TestAccount::new("saul", "k256");
+        let like = json(
+            r#"{"$type":"app.bsky.feed.like","subject":{"uri":"at://did:plc:x/app.bsky.feed.post/3kvr3ymffwc2m","cid":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"},"createdAt":"2024-08-01T12:00:00.000Z"}"#,
+        );
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", like);
+        account.commit(&rev_at(-60));
+        let proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let err = verify_record_in_archive(
+            archive(proof.clone()),
+            &uri,
+            &cid,
+            account.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error_text(err),
+            "record $type app.bsky.feed.like doesn't match collection app.bsky.feed.post"
+        );
+
+        let blob = hasher::cid_for(hasher::RAW, b"blob").to_string();
+        let err = verify_record_in_archive(
+            archive(proof),
+            &uri,
+            &blob,
+            account.did_doc(),
+            JsValue::UNDEFINED,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_text(err), "given cid is for a blob, not a record");
+    }
+
+    /// An MST node block with uncompressed keys, all holding `value`.
+    fn mst_node(l: Option<Cid>, entries: &[(&str, Option<Cid>)], value: Cid) -> (Cid, Vec<u8>) {
+        let link = |cid: Option<Cid>| cid.map_or(Value::Null, Value::Link);
+        let entries = entries
+            .iter()
+            .map(|(key, t)| {
+                let mut entry = BTreeMap::new();
+                entry.insert("k".into(), Value::Bytes(key.as_bytes().to_vec()));
+                entry.insert("p".into(), Value::Integer(0));
+                entry.insert("t".into(), link(*t));
+                entry.insert("v".into(), Value::Link(value));
+                Value::Map(entry)
+            })
+            .collect();
+        let mut node = BTreeMap::new();
+        node.insert("e".into(), Value::Array(entries));
+        node.insert("l".into(), link(l));
+        let block = value::encode_cbor(&Value::Map(node)).unwrap();
+        (cid_for_cbor(&block), block)
+    }
+
+    /// A proof of `record` signed with the k256 key of the account `name`,
+    /// over the tree `nodes`, the first of which is the root.
+    fn proof_with_tree(name: &str, nodes: &[(Cid, Vec<u8>)], record: &[u8]) -> Vec<u8> {
+        let key = SigningKey::from_bytes("k256", &sha2::Sha256::digest(name.as_bytes())).unwrap();
+        let commit = UnsignedCommitObject {
+            did: format!("did:plc:{}", name),
+            rev: rev_at(-60),
+            data: nodes[0].0,
+            prev: None,
+            version: 3,
+        };
+        let (root, block) = sign_commit(&commit, &key).unwrap();
+        let mut parts: Vec<(Cid, &[u8])> = vec![(root, &block[..]), (cid_for_cbor(record), record)];
+        parts.extend(nodes.iter().map(|(cid, node)| (*cid, &node[..])));
+        car::write_car(&[root], &parts).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_malformed_trees() {
+        let network = MockNetwork::install();
+        let account = TestAccount::new("omar", "k256");
+        account.publish(&network);
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2m");
+        let record = record_to_cbor(post("hello"), true).unwrap();
+        let cid = cid_for_cbor(&record);
+
+        // all on layer 0 but `layer_one`, and in this order
+        let before = "app.bsky.feed.post/3kvr3ymffwc2l";
+        let target = "app.bsky.feed.post/3kvr3ymffwc2m";
+        let layer_one = "app.bsky.feed.post/3kvr3ymffwc2n";
+        let after = "app.bsky.feed.post/3kvr3ymffwc2p";
+
+        let leaf = mst_node(None, &[(target, None)], cid);
+        let empty = mst_node(None, &[], cid);
+        let out_of_range = mst_node(None, &[(target, None), (after, None)], cid);
+        let cases = vec![
+            (
+                vec![mst_node(None, &[(target, None), (layer_one, None)], cid)],
+                MstError::WrongLayer,
+            ),
+            (
+                vec![mst_node(None, &[(target, None), (before, None)], cid)],
+                MstError::KeysOutOfOrder,
+            ),
+            (
+                vec![
+                    mst_node(Some(out_of_range.0), &[(layer_one, None)], cid),
+                    out_of_range,
+                ],
+                MstError::KeysOutOfOrder,
+            ),
+            (
+                vec![
+                    mst_node(Some(leaf.0), &[(layer_one, Some(empty.0))], cid),
+                    leaf,
+                    empty,
+                ],
+                MstError::EmptyNode,
+            ),
+        ];
+
+        for (nodes, err) in cases {
+            let proof = proof_with_tree("omar", &nodes, &record);
+            network.serve(&url, 200, "application/vnd.ipld.car", proof.clone());
+            let got = fetch_verified_record(&uri, JsValue::UNDEFINED)
+                .await
+                .unwrap_err();
+            assert_eq!(got, JsValue::from(err));
+
+            // archives are only read along the path to the record
+            if err == MstError::EmptyNode {
+                continue;
+            }
+            let got = verify_record_in_archive(
+                archive(proof),
+                &uri,
+                &cid.to_string(),
+                account.did_doc(),
+                JsValue::UNDEFINED,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(got, JsValue::from(err));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn caps_blob_downloads() {
+        let network = MockNetwork::install();
+        let account = TestAccount::new("olga", "k256");
+        account.publish(&network);
+        let bytes = vec![7; 1024];
+        let cid = hasher::cid_for(hasher::RAW, &bytes).to_string();
+        network.serve(&get_blob_url(&account.did, &cid), 200, "image/png", bytes);
+
+        let legacy = json(&format!(r#"{{"cid": "{}", "mimeType": "image/png"}}"#, cid));
+        verify_blob(&account.did, legacy.clone(), None, JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        let options = json(r#"{"maxBlobBytes": 512}"#);
+        let err = verify_blob(&account.did, legacy, None, options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "blob is larger than the size limit");
+    }
+
+    #[wasm_bindgen_test]
+    async fn verifies_every_blob_of_a_record() {
+        let network = MockNetwork::install();
+        let account = TestAccount::new("rosa", "k256");
+        account.publish(&network);
+        let good = hasher::cid_for(hasher::RAW, b"good").to_string();
+        let bad = hasher::cid_for(hasher::RAW, b"promised").to_string();
+        network.serve(
+            &get_blob_url(&account.did, &good),
+            200,
+            "image/png",
+            b"good".to_vec(),
+        );
+        network.serve(
+            &get_blob_url(&account.did, &bad),
+            200,
+            "image/png",
+            b"tampered".to_vec(),
+        );
+
+        let image = |cid: &str, size: i64| {
+            format!(
+                r#"{{"alt": "", "image": {{"$type": "blob", "ref": {{"$link": "{}"}}, "mimeType": "image/png", "size": {}}}}}"#,
+                cid, size
+            )
+        };
+        let record = |images: &[String]| {
+            json(&format!(
+                r#"{{"$type": "app.bsky.feed.post", "text": "", "createdAt": "2024-08-01T12:00:00.000Z", "embed": {{"$type": "app.bsky.embed.images", "images": [{}]}}}}"#,
+                images.join(",")
+            ))
+        };
+
+        verify_record_blobs(&account.did, record(&[image(&good, 4)]), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+        let both = record(&[image(&good, 4), image(&bad, 8)]);
+        let err = verify_record_blobs(&account.did, both, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "blob doesn't match its cid");
+
+        let negative = record(&[image(&good, -4)]);
+        let err = verify_record_blobs(&account.did, negative, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "invalid blob ref");
+        let negative = json(&image(&good, -4));
+        let negative = Reflect::get(&negative, &"image".into()).unwrap();
+        let err = verify_blob(&account.did, negative, None, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "invalid blob ref");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_blob_did_doc_for_another_did() {
+        let network = MockNetwork::install();
+        let account = TestAccount::new("peggy", "k256");
+        let other = TestAccount::new("quinn", "k256");
+        let did_doc = JSON::stringify(&other.did_doc()).unwrap();
+        network.serve(
+            &plc_url(&account.did),
+            200,
+            "application/json",
+            did_doc.as_string().unwrap().into_bytes(),
+        );
+        let cid = hasher::cid_for(hasher::RAW, b"blob").to_string();
+
+        let err = verify_blob(&account.did, cid.into(), None, JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "blob did doesn't match did doc id");
+        assert_eq!(network.requests(), [plc_url(&account.did)]);
+    }
+
+    /// Re-roots `proof` on a copy of its commit carrying `sig` instead.
+    async fn with_signature(proof: &[u8], sig: Vec<u8>) -> Vec<u8> {
+        let (roots, blocks) = car::read_car(proof).await.unwrap();
+        let old_root = roots[0].to_bytes();
+        let mut commit = value::decode_cbor(&blocks[&old_root]).unwrap();
+        if let Value::Map(ref mut map) = commit {
+            map.insert("sig".into(), Value::Bytes(sig));
+        }
+        let commit = value::encode_cbor(&commit).unwrap();
+        let root = cid_for_cbor(&commit);
+
+        let mut parts: Vec<(Cid, &[u8])> = vec![(root, &commit[..])];
+        for (cid, block) in blocks.iter().filter(|(cid, _)| **cid != old_root) {
+            parts.push((Cid::try_from(&cid[..]).unwrap(), &block[..]));
+        }
+        car::write_car(&[root], &parts).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_malformed_commits() {
+        let (network, account, cid) = published("nina", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let url = get_record_url(&account.did, POSTS, "3kvr3ymffwc2m");
+        let proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+
+        let not_a_commit = value::encode_cbor(&Value::String("not a commit".into())).unwrap();
+        let root = cid_for_cbor(&not_a_commit);
+        let cases = [
+            (
+                car::write_car(&[root], &[(root, &not_a_commit[..])]).unwrap(),
+                "couldn't decode commit",
+            ),
+            (
+                car::write_car(&[root], &[]).unwrap(),
+                "commit block missing from car",
+            ),
+            (
+                with_signature(&proof, vec![0; 64]).await,
+                "malformed signature",
+            ),
+        ];
+        for (car, expected) in cases {
+            network.serve(&url, 200, "application/vnd.ipld.car", car);
+            let err = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+                .await
+                .unwrap_err();
+            assert_eq!(error_text(err), expected);
+            let err = fetch_verified_record(&uri, JsValue::UNDEFINED)
+                .await
+                .unwrap_err();
+            assert_eq!(error_text(err), expected);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_malformed_signing_keys() {
+        let (_network, account, cid) = published("oscar", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let p256_garbage = [&[0x80, 0x24][..], &[0x05; 33]].concat();
+        let cases = [
+            (String::new(), "couldn't decode signing key"),
+            (multibase(&[0xe7]), "unknown signing key format"),
+            (multibase(&[0xe7, 0x01, 0x02]), "invalid k256 signing key"),
+            (multibase(&p256_garbage), "invalid p256 signing key"),
+        ];
+        for (key, expected) in cases {
+            let did_doc = account.did_doc_with_key(&key);
+            let err =
+                authenticate_post_with_doc(&uri, &cid, post("hello"), did_doc, JsValue::UNDEFINED)
+                    .await
+                    .unwrap_err();
+            assert_eq!(error_text(err), expected);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn enforces_car_limits() {
+        let (_network, account, cid) = published("karl", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+
+        let options = json(r#"{"maxCarBytes": 64}"#);
+        let err = authenticate_post(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "car is larger than the size limit");
+    }
+
+    #[wasm_bindgen_test]
+    async fn enforces_car_limits_while_streaming() {
+        let (network, account, cid) = published("kim", "k256");
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        network.chunk(&get_record_url(&account.did, POSTS, "3kvr3ymffwc2m"), 7);
+
+        authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap();
+
+        let options = json(r#"{"maxCarBytes": 64}"#);
+        let err = authenticate_post(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "car is larger than the size limit");
+
+        let options = json(r#"{"maxCarBlocks": 1}"#);
+        let err = authenticate_post(&uri, &cid, post("hello"), options)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "car has more blocks than the limit");
+    }
+
+    #[wasm_bindgen_test]
+    async fn rejects_corrupted_car() {
+        let network = MockNetwork::install();
+        let mut account = TestAccount::new("lena", "k256");
+        let cid = account.put(POSTS, "3kvr3ymffwc2m", post("hello"));
+        account.commit(&rev_at(-60));
+        account.publish(&network);
+
+        let mut proof = account
+            .repo
+            .get_record_proof(POSTS, "3kvr3ymffwc2m")
+            .unwrap();
+        let last = proof.len() - 1;
+        proof[last] ^= 1;
+        network.serve(
+            &get_record_url(&account.did, POSTS, "3kvr3ymffwc2m"),
+            200,
+            "application/vnd.ipld.car",
+            proof,
+        );
+
+        let uri = account.uri(POSTS, "3kvr3ymffwc2m");
+        let err = authenticate_post(&uri, &cid, post("hello"), JsValue::UNDEFINED)
+            .await
+            .unwrap_err();
+        assert_eq!(error_text(err), "a cid in the car doesn't match its record");
+    }
+}
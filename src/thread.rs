@@ -0,0 +1,239 @@
+use crate::ipld_transcode::{format_path, Segment};
+use crate::syntax::AtUri;
+use crate::transport::is_abort;
+use crate::value::{self, Json, Value};
+use crate::{fetch_verified, VerifyOptions};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::js_sys::Error;
+
+const DEFAULT_MAX_DEPTH: u32 = 8;
+const DEFAULT_MAX_NODES: usize = 50;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_THREAD: &str = r#"
+export interface ThreadOptions extends VerifyOptions {
+  maxDepth?: number;
+  maxNodes?: number;
+}
+
+export interface ThreadNode {
+  uri: string;
+  depth: number;
+  status: "verified" | "failed" | "skipped";
+  cid?: string;
+  commitRev?: string;
+  record?: AtprotoRecord;
+  error?: string;
+}
+
+export interface ThreadEdge {
+  from: string;
+  to: string;
+  path: string;
+  cid: string;
+  cidMatches: boolean | null;
+}
+
+export interface ThreadGraph {
+  root: string;
+  nodes: ThreadNode[];
+  edges: ThreadEdge[];
+}
+
+export function verify_thread(uri: string, options?: ThreadOptions): Promise<ThreadGraph>;
+"#;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ThreadOptions {
+    max_depth: Option<u32>,
+    max_nodes: Option<usize>,
+}
+
+/// A `com.atproto.repo.strongRef` found somewhere in a record.
+struct StrongRef {
+    path: String,
+    uri: String,
+    cid: String,
+}
+
+/// Collects every `{uri, cid}` pair in `value` whose uri names a record, e.g.
+/// `reply.parent`, `embed.record` or a like's `subject`.
+fn strong_refs(value: &Value, path: &mut Vec<Segment>, refs: &mut Vec<StrongRef>) {
+    match value {
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                path.push(Segment::Index(index));
+                strong_refs(item, path, refs);
+                path.pop();
+            }
+        }
+        Value::Map(map) => {
+            if let (Some(Value::String(uri)), Some(Value::String(cid))) =
+                (map.get("uri"), map.get("cid"))
+            {
+                if AtUri::from_str(uri).is_ok_and(|uri| uri.record().is_ok()) {
+                    refs.push(StrongRef {
+                        path: format_path(path),
+                        uri: uri.clone(),
+                        cid: cid.clone(),
+                    });
+                    return;
+                }
+            }
+            for (key, item) in map.iter() {
+                path.push(Segment::Key(key.clone()));
+                strong_refs(item, path, refs);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn serialize_record<S: Serializer>(record: &Option<Value>, s: S) -> Result<S::Ok, S::Error> {
+    record.as_ref().map(Json).serialize(s)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadNode {
+    uri: String,
+    depth: u32,
+    /// `verified`, `failed`, or `skipped` once a depth or size limit is hit.
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    commit_rev: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_record"
+    )]
+    record: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadEdge {
+    from: String,
+    to: String,
+    path: String,
+    cid: String,
+    /// Whether the record at `to` still has the cid the ref pinned, once
+    /// it has been verified.
+    cid_matches: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ThreadGraph {
+    root: String,
+    nodes: Vec<ThreadNode>,
+    edges: Vec<ThreadEdge>,
+}
+
+/// Verifies the record at `uri`, then every record it points at through a
+/// strong ref (reply parent and root, quoted records, a like's subject, ...)
+/// and so on recursively, each at most once. Returns `{root, nodes, edges}`,
+/// where every node is `verified`, `failed` (with an `error`) or `skipped`
+/// past `maxDepth` (default 8) or `maxNodes` (default 50). Takes the same
+/// options as `fetch_verified_record` besides.
+#[wasm_bindgen(skip_typescript)]
+pub async fn verify_thread(uri: &str, options: JsValue) -> Result<JsValue, JsValue> {
+    let thread_options: ThreadOptions = if options.is_undefined() || options.is_null() {
+        ThreadOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options.clone())?
+    };
+    let options = VerifyOptions::from_js(options)?;
+    let max_depth = thread_options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    let max_nodes = thread_options.max_nodes.unwrap_or(DEFAULT_MAX_NODES);
+
+    let mut nodes: Vec<ThreadNode> = Vec::new();
+    let mut edges: Vec<ThreadEdge> = Vec::new();
+    let mut seen = HashSet::from([uri.to_owned()]);
+    let mut queue = VecDeque::from([(uri.to_owned(), 0)]);
+    let mut attempted = 0;
+
+    while let Some((uri, depth)) = queue.pop_front() {
+        if depth > max_depth || attempted >= max_nodes {
+            nodes.push(ThreadNode {
+                uri,
+                depth,
+                status: "skipped",
+                cid: None,
+                commit_rev: None,
+                record: None,
+                error: None,
+            });
+            continue;
+        }
+
+        attempted += 1;
+        let fetched = match fetch_verified(&uri, &options).await {
+            Ok(fetched) => fetched,
+            Err(err) if is_abort(&err) => return Err(err),
+            Err(err) => {
+                let error = err
+                    .as_string()
+                    .or_else(|| err.dyn_ref::<Error>().map(|err| err.to_string().into()))
+                    .unwrap_or_else(|| format!("{:?}", err));
+                nodes.push(ThreadNode {
+                    uri,
+                    depth,
+                    status: "failed",
+                    cid: None,
+                    commit_rev: None,
+                    record: None,
+                    error: Some(error),
+                });
+                continue;
+            }
+        };
+
+        let mut refs = Vec::new();
+        strong_refs(&fetched.record, &mut Vec::new(), &mut refs);
+        for strong_ref in refs {
+            if seen.insert(strong_ref.uri.clone()) {
+                queue.push_back((strong_ref.uri.clone(), depth + 1));
+            }
+            edges.push(ThreadEdge {
+                from: uri.clone(),
+                to: strong_ref.uri,
+                path: strong_ref.path,
+                cid: strong_ref.cid,
+                cid_matches: None,
+            });
+        }
+
+        nodes.push(ThreadNode {
+            uri,
+            depth,
+            status: "verified",
+            cid: Some(fetched.cid.to_string()),
+            commit_rev: Some(fetched.commit_rev),
+            record: Some(fetched.record),
+            error: None,
+        });
+    }
+
+    for edge in edges.iter_mut() {
+        edge.cid_matches = nodes
+            .iter()
+            .find(|node| node.uri == edge.to)
+            .and_then(|node| node.cid.as_ref())
+            .map(|cid| *cid == edge.cid);
+    }
+
+    value::serialize_js(&ThreadGraph {
+        root: uri.to_owned(),
+        nodes,
+        edges,
+    })
+}
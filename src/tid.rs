@@ -0,0 +1,47 @@
+use crate::syntax::{Tid, TID_ALPHABET};
+use std::str::FromStr;
+
+impl Tid {
+    /// Builds the TID for a microsecond timestamp and a 10 bit clock id.
+    pub fn new(timestamp_micros: u64, clock_id: u16) -> Tid {
+        let mut value = ((timestamp_micros & ((1 << 53) - 1)) << 10) | (clock_id as u64 & 0x3ff);
+        let mut chars = [0u8; 13];
+        for c in chars.iter_mut().rev() {
+            *c = TID_ALPHABET[(value & 31) as usize];
+            value >>= 5;
+        }
+        Tid::from_str(std::str::from_utf8(&chars).unwrap()).unwrap()
+    }
+
+    fn value(&self) -> u64 {
+        self.as_str().bytes().fold(0, |value, c| {
+            (value << 5) | TID_ALPHABET.iter().position(|&a| a == c).unwrap() as u64
+        })
+    }
+
+    /// Microseconds since the unix epoch.
+    pub fn timestamp_micros(&self) -> u64 {
+        self.value() >> 10
+    }
+
+    pub fn clock_id(&self) -> u16 {
+        (self.value() & 0x3ff) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn decode() {
+        let tid = Tid::from_str("3jzfcijpj2z2a").unwrap();
+        assert_eq!(tid.timestamp_micros(), 1688137381887007);
+        assert_eq!(tid.clock_id(), 6);
+        assert_eq!(Tid::new(1688137381887007, 6), tid);
+
+        let zero = Tid::from_str("2222222222222").unwrap();
+        assert_eq!((zero.timestamp_micros(), zero.clock_id()), (0, 0));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::xrpc;
+use futures_util::future::{self, Either, FutureExt, LocalBoxFuture};
+use std::cell::RefCell;
+use std::future::Future;
+use std::ops::Deref;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{self, Date, Function, Promise, Reflect, Uint8Array};
+use web_sys::{
+    AbortController, AbortSignal, ReadableStreamDefaultReader, Request, RequestInit, RequestMode,
+    Response,
+};
+
+/// How HTTP requests leave the verifier. Tests swap in an in-memory one.
+/// Implementations should give up on the request once `signal` aborts.
+pub trait Transport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        signal: &'a AbortSignal,
+    ) -> LocalBoxFuture<'a, Result<Response, JsValue>>;
+}
+
+/// The global `fetch`, so windows and workers alike can use it.
+pub struct FetchTransport;
+
+impl Transport for FetchTransport {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        signal: &'a AbortSignal,
+    ) -> LocalBoxFuture<'a, Result<Response, JsValue>> {
+        async move {
+            let opts = RequestInit::new();
+            opts.set_method("GET");
+            opts.set_mode(RequestMode::Cors);
+            opts.set_signal(Some(signal));
+
+            let global = js_sys::global();
+            let fetch: Function = Reflect::get(&global, &"fetch".into())?
+                .dyn_into()
+                .map_err(|_| "fetch is not available")?;
+
+            let request = Request::new_with_str_and_init(url, &opts)?;
+
+            let promise: Promise = fetch.call1(&global, &request)?.unchecked_into();
+            let resp_value = JsFuture::from(promise).await?;
+            if !resp_value.is_instance_of::<Response>() {
+                return Err("could not get response".into());
+            }
+            Ok(resp_value.dyn_into().unwrap())
+        }
+        .boxed_local()
+    }
+}
+
+thread_local! {
+    static TRANSPORT: RefCell<Rc<dyn Transport>> = RefCell::new(Rc::new(FetchTransport));
+}
+
+pub fn set_transport(transport: Rc<dyn Transport>) {
+    TRANSPORT.with(|cell| *cell.borrow_mut() = transport);
+}
+
+const DEFAULT_TIMEOUT_MS: u32 = 15_000;
+const DEFAULT_RETRIES: u32 = 2;
+const BACKOFF_MS: u32 = 250;
+
+/// How patient to be with each request of a verification.
+pub struct FetchOptions {
+    pub timeout_ms: u32,
+    /// Extra attempts after a network error, a timeout, a 5xx or a 429.
+    pub retries: u32,
+    /// Cancels every outstanding and future request when it aborts.
+    pub signal: Option<AbortSignal>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> FetchOptions {
+        FetchOptions {
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            retries: DEFAULT_RETRIES,
+            signal: None,
+        }
+    }
+}
+
+fn aborted() -> JsValue {
+    let error = js_sys::Error::new("request aborted");
+    error.set_name("AbortError");
+    error.into()
+}
+
+/// Whether `err` came from the caller cancelling a request.
+pub fn is_abort(err: &JsValue) -> bool {
+    err.dyn_ref::<js_sys::Error>()
+        .is_some_and(|err| err.name() == "AbortError")
+}
+
+/// Resolves after `ms` milliseconds. Uses the global `setTimeout` so it works
+/// outside of windows too.
+pub fn sleep(ms: u32) -> JsFuture {
+    JsFuture::from(Promise::new(&mut |resolve: Function, _| {
+        if let Ok(set_timeout) = Reflect::get(&js_sys::global(), &"setTimeout".into()) {
+            let set_timeout: Function = set_timeout.unchecked_into();
+            let _ = set_timeout.call2(&JsValue::NULL, &resolve, &ms.into());
+        }
+    }))
+}
+
+/// Resolves once `signal` aborts, never if there is no signal. Dropping it
+/// detaches the listener.
+struct AbortWait {
+    signal: Option<AbortSignal>,
+    listener: Option<Function>,
+    promise: JsFuture,
+}
+
+impl AbortWait {
+    fn new(signal: Option<&AbortSignal>) -> AbortWait {
+        let mut listener = None;
+        let promise = Promise::new(&mut |resolve: Function, _| {
+            if let Some(signal) = signal {
+                let _ = signal.add_event_listener_with_callback("abort", &resolve);
+                listener = Some(resolve);
+            }
+        });
+        AbortWait {
+            signal: signal.cloned(),
+            listener,
+            promise: JsFuture::from(promise),
+        }
+    }
+}
+
+impl Drop for AbortWait {
+    fn drop(&mut self) {
+        if let (Some(signal), Some(listener)) = (&self.signal, &self.listener) {
+            let _ = signal.remove_event_listener_with_callback("abort", listener);
+        }
+    }
+}
+
+enum Attempt {
+    Done(Response, AbortController),
+    Failed(JsValue),
+    TimedOut,
+    Aborted,
+}
+
+async fn attempt(transport: &dyn Transport, url: &str, options: &FetchOptions) -> Attempt {
+    if options
+        .signal
+        .as_ref()
+        .is_some_and(|signal| signal.aborted())
+    {
+        return Attempt::Aborted;
+    }
+    let controller = match AbortController::new() {
+        Ok(controller) => controller,
+        Err(err) => return Attempt::Failed(err),
+    };
+    let signal = controller.signal();
+
+    let mut abort_wait = AbortWait::new(options.signal.as_ref());
+    let request = transport.get(url, &signal);
+    let timeout = sleep(options.timeout_ms);
+    let stop = future::select(timeout, &mut abort_wait.promise);
+
+    let outcome = match future::select(request, stop).await {
+        Either::Left((Ok(resp), _)) => return Attempt::Done(resp, controller),
+        Either::Left((Err(err), _)) => Attempt::Failed(err),
+        Either::Right((Either::Left(_), _)) => Attempt::TimedOut,
+        Either::Right((Either::Right(_), _)) => Attempt::Aborted,
+    };
+    controller.abort();
+    outcome
+}
+
+fn timed_out(url: &str, timeout_ms: u32) -> JsValue {
+    format!("request to {} timed out after {}ms", url, timeout_ms).into()
+}
+
+/// A response whose body is still on its way. Reads of the body go through
+/// `read`, so the caller's signal and the request's timeout keep applying
+/// until the body is consumed.
+pub struct FetchResponse {
+    resp: Response,
+    controller: AbortController,
+    signal: Option<AbortSignal>,
+    url: String,
+    timeout_ms: u32,
+    deadline: f64,
+}
+
+impl Deref for FetchResponse {
+    type Target = Response;
+
+    fn deref(&self) -> &Response {
+        &self.resp
+    }
+}
+
+impl FetchResponse {
+    /// Waits for `body`, a read of this response's body, cancelling the
+    /// download if the caller aborts or the request outlives its timeout.
+    pub async fn read<T>(
+        &self,
+        body: impl Future<Output = Result<T, JsValue>>,
+    ) -> Result<T, JsValue> {
+        if self.signal.as_ref().is_some_and(|signal| signal.aborted()) {
+            self.controller.abort();
+            return Err(aborted());
+        }
+        let remaining = (self.deadline - Date::now()).max(0.0) as u32;
+        let mut abort_wait = AbortWait::new(self.signal.as_ref());
+        let timeout = sleep(remaining);
+        let stop = future::select(timeout, &mut abort_wait.promise);
+
+        let error = match future::select(Box::pin(body), stop).await {
+            Either::Left((result, _)) => return result,
+            Either::Right((Either::Left(_), _)) => timed_out(&self.url, self.timeout_ms),
+            Either::Right((Either::Right(_), _)) => aborted(),
+        };
+        self.controller.abort();
+        Err(error)
+    }
+}
+
+/// GETs `url` through the current transport, retrying transient failures
+/// with exponential backoff, and fails on anything but a 2xx response. A
+/// `Retry-After` longer than the timeout is given up on straight away. The
+/// timeout covers each attempt from sending the request to the end of the
+/// body.
+pub async fn fetch(url: &str, options: &FetchOptions) -> Result<FetchResponse, JsValue> {
+    let transport = TRANSPORT.with(|cell| cell.borrow().clone());
+
+    let mut tries = 0;
+    loop {
+        let mut delay = BACKOFF_MS << tries.min(8);
+        let started = Date::now();
+        let error = match attempt(&*transport, url, options).await {
+            Attempt::Done(resp, controller) => {
+                let resp = FetchResponse {
+                    resp,
+                    controller,
+                    signal: options.signal.clone(),
+                    url: url.to_owned(),
+                    timeout_ms: options.timeout_ms,
+                    deadline: started + options.timeout_ms as f64,
+                };
+                if resp.ok() {
+                    return Ok(resp);
+                }
+                let transient = resp.status() >= 500 || resp.status() == 429;
+                let retry_after = xrpc::retry_after_ms(&resp);
+                let error = resp
+                    .read(xrpc::response_error(url, &resp).map(Ok))
+                    .await
+                    .unwrap_or_else(|err| err);
+                if is_abort(&error)
+                    || !transient
+                    || retry_after.is_some_and(|ms| ms > options.timeout_ms)
+                {
+                    return Err(error);
+                }
+                delay = delay.max(retry_after.unwrap_or(0));
+                error
+            }
+            Attempt::Failed(err) => err,
+            Attempt::TimedOut => timed_out(url, options.timeout_ms),
+            Attempt::Aborted => return Err(aborted()),
+        };
+        if tries >= options.retries {
+            return Err(error);
+        }
+
+        let mut abort_wait = AbortWait::new(options.signal.as_ref());
+        let backoff = sleep(delay);
+        if let Either::Right(_) = future::select(backoff, &mut abort_wait.promise).await {
+            return Err(aborted());
+        }
+        tries += 1;
+    }
+}
+
+/// Feeds the body of `resp` to `on_chunk` as it arrives, and cancels the
+/// download as soon as `on_chunk` fails.
+pub async fn read_chunks(
+    resp: &Response,
+    mut on_chunk: impl FnMut(&[u8]) -> Result<(), JsValue>,
+) -> Result<(), JsValue> {
+    let body = match resp.body() {
+        Some(body) => body,
+        None => return Err("response has no body".into()),
+    };
+    let reader: ReadableStreamDefaultReader = body.get_reader().unchecked_into();
+
+    loop {
+        let result = JsFuture::from(reader.read()).await?;
+        let done = Reflect::get(&result, &"done".into())?;
+        if done.as_bool().unwrap_or(false) {
+            return Ok(());
+        }
+        let chunk = Uint8Array::new(&Reflect::get(&result, &"value".into())?).to_vec();
+        if let Err(err) = on_chunk(&chunk) {
+            let _ = reader.cancel();
+            return Err(err);
+        }
+    }
+}
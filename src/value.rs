@@ -0,0 +1,321 @@
+use cid::Cid;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use wasm_bindgen::prelude::*;
+
+/// A value of the atproto data model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Bytes(Vec<u8>),
+    Link(Cid),
+    Array(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+/// Decodes the unpadded standard base64 used by `$bytes`, tolerating padding.
+pub fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    libipld::multibase::Base::Base64
+        .decode(input.trim_end_matches('='))
+        .ok()
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    libipld::multibase::Base::Base64.encode(bytes)
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "an atproto data model value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        i64::try_from(v)
+            .map(Value::Integer)
+            .map_err(|_| E::custom("integer out of range"))
+    }
+
+    fn visit_i128<E: de::Error>(self, v: i128) -> Result<Value, E> {
+        i64::try_from(v)
+            .map(Value::Integer)
+            .map_err(|_| E::custom("integer out of range"))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Value, E> {
+        Err(E::custom("floats are not allowed in the atproto data model"))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        Value::deserialize(d)
+    }
+
+    // serde_ipld_dagcbor hands out cids (tag 42) as a newtype around their bytes
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Value, D::Error> {
+        let bytes = serde_bytes::ByteBuf::deserialize(d)?;
+        Cid::try_from(bytes.as_slice())
+            .map(Value::Link)
+            .map_err(|_| de::Error::custom("invalid cid"))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries = BTreeMap::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value: Value = map.next_value()?;
+            entries.insert(key, value);
+        }
+
+        // the JSON encodings of links and bytes
+        if entries.len() == 1 {
+            if let Some(Value::String(link)) = entries.get("$link") {
+                return Cid::from_str(link)
+                    .map(Value::Link)
+                    .map_err(|_| de::Error::custom("$link must have valid cid"));
+            }
+            if let Some(Value::String(bytes)) = entries.get("$bytes") {
+                return decode_base64(bytes)
+                    .map(Value::Bytes)
+                    .ok_or_else(|| de::Error::custom("$bytes must be valid base64"));
+            }
+        }
+
+        Ok(Value::Map(entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Value, D::Error> {
+        d.deserialize_any(ValueVisitor)
+    }
+}
+
+/// Serializes a `Value` in its atproto JSON form, with `$link` and `$bytes`
+/// objects for links and bytes.
+pub struct Json<'a>(pub &'a Value);
+
+impl<'a> Serialize for Json<'a> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => s.serialize_none(),
+            Value::Bool(v) => s.serialize_bool(*v),
+            Value::Integer(v) => s.serialize_i64(*v),
+            Value::String(v) => s.serialize_str(v),
+            Value::Bytes(v) => {
+                let mut map = s.serialize_map(Some(1))?;
+                map.serialize_entry("$bytes", &encode_base64(v))?;
+                map.end()
+            }
+            Value::Link(v) => {
+                let mut map = s.serialize_map(Some(1))?;
+                map.serialize_entry("$link", &v.to_string())?;
+                map.end()
+            }
+            Value::Array(items) => s.collect_seq(items.iter().map(Json)),
+            Value::Map(entries) => s.collect_map(entries.iter().map(|(k, v)| (k, Json(v)))),
+        }
+    }
+}
+
+/// Serializes a `Value` as DAG-CBOR, with map keys in canonical order.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => s.serialize_none(),
+            Value::Bool(v) => s.serialize_bool(*v),
+            Value::Integer(v) => s.serialize_i64(*v),
+            Value::String(v) => s.serialize_str(v),
+            Value::Bytes(v) => s.serialize_bytes(v),
+            Value::Link(v) => v.serialize(s),
+            Value::Array(items) => s.collect_seq(items),
+            Value::Map(entries) => {
+                // shorter keys first, then bytewise
+                let mut entries: Vec<_> = entries.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| {
+                    (a.len(), a.as_bytes()).cmp(&(b.len(), b.as_bytes()))
+                });
+                s.collect_map(entries)
+            }
+        }
+    }
+}
+
+/// Converts anything serializable into a plain JS object, the way records are
+/// handed back to callers.
+pub fn serialize_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    let serializer = serde_wasm_bindgen::Serializer::new()
+        .serialize_maps_as_objects(true)
+        .serialize_missing_as_null(true);
+    Ok(value.serialize(&serializer)?)
+}
+
+pub fn to_js(value: &Value) -> Result<JsValue, JsValue> {
+    serialize_js(&Json(value))
+}
+
+pub fn decode_cbor(bytes: &[u8]) -> Result<Value, JsValue> {
+    serde_ipld_dagcbor::from_slice(bytes)
+        .map_err(|err| format!("couldn't decode cbor: {}", err).into())
+}
+
+pub fn encode_cbor(value: &Value) -> Result<Vec<u8>, JsValue> {
+    serde_ipld_dagcbor::to_vec(value)
+        .map_err(|err| format!("couldn't encode cbor: {}", err).into())
+}
+
+/// Checks that `bytes` is the one canonical DAG-CBOR encoding of what it
+/// decodes to: sorted map keys, definite lengths and minimal integers.
+/// Decoding alone accepts all of these, so two different blocks (and cids)
+/// could otherwise stand for the same data.
+pub fn check_canonical(bytes: &[u8]) -> Result<(), JsValue> {
+    if encode_cbor(&decode_cbor(bytes)?)? != bytes {
+        return Err("block is not canonical dag-cbor".into());
+    }
+    Ok(())
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_VALUE: &str = r#"
+export function cbor_to_json(bytes: Uint8Array): unknown;
+"#;
+
+/// Renders a DAG-CBOR block as atproto JSON (links as `$link`, bytes as
+/// `$bytes`).
+#[wasm_bindgen(skip_typescript)]
+pub fn cbor_to_json(bytes: &[u8]) -> Result<JsValue, JsValue> {
+    to_js(&decode_cbor(bytes)?)
+}
+
+fn check_blob(map: &BTreeMap<String, Value>) -> Result<(), String> {
+    for key in map.keys() {
+        if !["$type", "ref", "mimeType", "size"].contains(&key.as_str()) {
+            return Err(format!("blob has unexpected field {}", key));
+        }
+    }
+    match map.get("ref") {
+        Some(Value::Link(cid)) if cid.codec() == crate::hasher::RAW => {}
+        Some(Value::Link(_)) => return Err("blob ref must use the raw codec".into()),
+        _ => return Err("blob must have a ref link".into()),
+    }
+    match map.get("mimeType") {
+        Some(Value::String(mime_type)) if !mime_type.is_empty() => {}
+        _ => return Err("blob must have a mimeType".into()),
+    }
+    match map.get("size") {
+        Some(Value::Integer(size)) if *size >= 0 => {}
+        _ => return Err("blob must have a size".into()),
+    }
+    Ok(())
+}
+
+/// Checks that every blob reference in `value`, current or legacy, is well
+/// formed.
+pub fn validate_blobs(value: &Value) -> Result<(), String> {
+    match value {
+        Value::Array(items) => items.iter().try_for_each(validate_blobs),
+        Value::Map(map) => {
+            if map.get("$type") == Some(&Value::String("blob".into())) {
+                check_blob(map)?;
+            } else if map.len() == 2 && map.contains_key("cid") && map.contains_key("mimeType") {
+                // legacy blobs: {cid: "<cid string>", mimeType}
+                match (map.get("cid"), map.get("mimeType")) {
+                    (Some(Value::String(cid)), Some(Value::String(_))) => {
+                        if Cid::from_str(cid).is_err() {
+                            return Err("legacy blob has an invalid cid".into());
+                        }
+                    }
+                    _ => return Err("legacy blob must have string cid and mimeType".into()),
+                }
+            }
+            map.values().try_for_each(validate_blobs)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys::js_sys::JSON;
+
+    #[wasm_bindgen_test]
+    fn round_trip() {
+        let json = r#"{"$type":"app.bsky.feed.post","bytes":{"$bytes":"nFERjvLLiw9qm45JrqH9QTzyC2Lu1Xb4ne6+sBrCzI0"},"embed":{"$type":"blob","mimeType":"image/png","ref":{"$link":"bafkreif32i7xs4ltlattqepkodgsqt5o7j44bfwdigjdz3u7vrgim4xwwm"},"size":12345},"langs":["en"],"reply":null}"#;
+        let cbor = crate::record_to_cbor(JSON::parse(json).unwrap(), true).unwrap();
+        let rendered = cbor_to_json(&cbor).unwrap();
+        assert_eq!(
+            JSON::stringify(&rendered).unwrap().as_string().unwrap(),
+            json
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn canonical_encoding() {
+        // {"b": 2, "aa": 1}: shorter keys sort first
+        check_canonical(&[0xa2, 0x61, 0x62, 0x02, 0x62, 0x61, 0x61, 0x01]).unwrap();
+
+        let rejected: &[&[u8]] = &[
+            // keys in plain bytewise order
+            &[0xa2, 0x62, 0x61, 0x61, 0x01, 0x61, 0x62, 0x02],
+            // keys out of order
+            &[0xa2, 0x61, 0x62, 0x01, 0x61, 0x61, 0x02],
+            // 1 encoded in two bytes
+            &[0xa1, 0x61, 0x61, 0x18, 0x01],
+            // indefinite length array
+            &[0xa1, 0x61, 0x61, 0x9f, 0x01, 0xff],
+        ];
+        for block in rejected {
+            assert!(check_canonical(block).is_err());
+        }
+    }
+}
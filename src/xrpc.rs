@@ -0,0 +1,113 @@
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::js_sys::{self, Date, Reflect, JSON};
+use web_sys::Response;
+
+/// The body XRPC servers send with an error status.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ErrorBody {
+    error: Option<String>,
+    message: Option<String>,
+}
+
+/// How long the server asked us to wait before trying again, from
+/// `Retry-After` (seconds or an HTTP date) or the `RateLimit-Reset` epoch
+/// seconds atproto servers send.
+pub fn retry_after_ms(resp: &Response) -> Option<u32> {
+    let header = |name| resp.headers().get(name).ok().flatten();
+    let now = Date::now();
+    let until = if let Some(retry_after) = header("retry-after") {
+        match retry_after.trim().parse::<f64>() {
+            Ok(secs) => now + secs * 1000.0,
+            Err(_) => Date::parse(&retry_after),
+        }
+    } else {
+        header("ratelimit-reset")?.trim().parse::<f64>().ok()? * 1000.0
+    };
+    if until.is_nan() {
+        return None;
+    }
+    Some((until - now).clamp(0.0, u32::MAX as f64) as u32)
+}
+
+/// Turns a non-2xx response into an error. XRPC error bodies become a JS
+/// `Error` named after their `error` code (`RecordNotFound`,
+/// `RepoTakendown`, `RepoDeactivated`, ...) with `status`, `error` and, when
+/// known, `retryAfter` in seconds. A 429 is always a `RateLimited` error,
+/// keeping any more specific code from the server in `error`. Anything else
+/// is a plain message.
+pub async fn response_error(url: &str, resp: &Response) -> JsValue {
+    let status = resp.status();
+    let retry_after = retry_after_ms(resp);
+
+    let text = match resp.text() {
+        Ok(text) => JsFuture::from(text)
+            .await
+            .ok()
+            .and_then(|text| text.as_string()),
+        Err(_) => None,
+    };
+    let body: ErrorBody = text
+        .and_then(|text| JSON::parse(&text).ok())
+        .and_then(|json| serde_wasm_bindgen::from_value(json).ok())
+        .unwrap_or_default();
+
+    let (name, code) = match (status, body.error) {
+        (429, code) => {
+            let code = code.unwrap_or_else(|| "RateLimited".into());
+            ("RateLimited".to_owned(), code)
+        }
+        (_, Some(code)) => (code.clone(), code),
+        (_, None) => {
+            return match body.message {
+                Some(message) => format!("{} returned HTTP {}: {}", url, status, message).into(),
+                None => format!("{} returned HTTP {}", url, status).into(),
+            }
+        }
+    };
+
+    let message = body
+        .message
+        .unwrap_or_else(|| format!("{} returned HTTP {}", url, status));
+    let error = js_sys::Error::new(&message);
+    error.set_name(&name);
+    let _ = Reflect::set(&error, &"status".into(), &status.into());
+    let _ = Reflect::set(&error, &"error".into(), &code.into());
+    if let Some(retry_after) = retry_after {
+        let secs = (retry_after as f64 / 1000.0).ceil();
+        let _ = Reflect::set(&error, &"retryAfter".into(), &secs.into());
+    }
+    error.into()
+}
+
+fn mime_type(resp: &Response) -> Option<String> {
+    let content_type = resp.headers().get("content-type").ok().flatten()?;
+    let mime_type = content_type.split(';').next().unwrap_or("").trim();
+    Some(mime_type.to_ascii_lowercase())
+}
+
+fn wrong_content_type(url: &str, resp: &Response, expected: &str) -> JsValue {
+    let got = mime_type(resp).unwrap_or_else(|| "no content type".into());
+    format!("{} returned {} instead of {}", url, got, expected).into()
+}
+
+/// Fails unless `resp` is a CAR file, e.g. rather than an HTML error page.
+pub fn expect_car(url: &str, resp: &Response) -> Result<(), JsValue> {
+    match mime_type(resp).as_deref() {
+        Some("application/vnd.ipld.car") => Ok(()),
+        _ => Err(wrong_content_type(url, resp, "a car file")),
+    }
+}
+
+/// Fails unless `resp` is JSON, including `+json` types like
+/// `application/did+ld+json`.
+pub fn expect_json(url: &str, resp: &Response) -> Result<(), JsValue> {
+    match mime_type(resp) {
+        Some(mime_type) if mime_type == "application/json" || mime_type.ends_with("+json") => {
+            Ok(())
+        }
+        _ => Err(wrong_content_type(url, resp, "json")),
+    }
+}